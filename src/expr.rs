@@ -1,4 +1,5 @@
-use std::collections::HashMap;
+use std::cmp::Ordering;
+use std::collections::{BTreeSet, HashMap};
 use std::fmt;
 
 use super::lexer::*;
@@ -54,7 +55,7 @@ impl fmt::Display for SyntaxError {
 }
 
 // TODO: unary minus
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum Op {
     Add,
     Sub,
@@ -67,7 +68,7 @@ pub enum Op {
 }
 
 impl Op {
-    fn from_token_kind(kind: TokenKind) -> Option<Self> {
+    pub fn from_token_kind(kind: TokenKind) -> Option<Self> {
         match kind {
             TokenKind::Plus => Some(Op::Add),
             TokenKind::Dash => Some(Op::Sub),
@@ -107,7 +108,7 @@ impl fmt::Display for Op {
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum Expr {
     Sym(String),
     Var(String),
@@ -145,6 +146,24 @@ impl Expr {
         }
     }
 
+    /// Whether the expression is `subexpr` or has it inside
+    pub fn contains(&self, subexpr: &Expr) -> bool {
+        self == subexpr || self.subexprs().iter().any(|expr| expr.contains(subexpr))
+    }
+
+    /// Replaces every occurrence of `from` with `to`
+    pub fn replace(&self, from: &Expr, to: &Expr) -> Self {
+        if self == from {
+            return to.clone();
+        }
+        let subexprs = self
+            .subexprs()
+            .iter()
+            .map(|expr| expr.replace(from, to))
+            .collect();
+        self.with_subexprs(subexprs)
+    }
+
     pub fn var_or_sym_based_on_name(name: &str) -> Self {
         let x = name
             .chars()
@@ -198,6 +217,7 @@ impl Expr {
 
     fn parse_fun_or_var_or_sym(
         lexer: &mut Lexer<impl Iterator<Item = char>>,
+        line: Option<usize>,
     ) -> Result<Self, SyntaxError> {
         let mut head = {
             let token = lexer.peek_token().clone();
@@ -223,7 +243,9 @@ impl Expr {
             }
         };
 
-        while lexer.peek_token().kind == TokenKind::OpenParen {
+        while lexer.peek_token().kind == TokenKind::OpenParen
+            && line.is_none_or(|line| lexer.peek_token().loc.row == line)
+        {
             head = Expr::Fun(Box::new(head), Self::parse_fun_args(lexer)?)
         }
         Ok(head)
@@ -232,12 +254,13 @@ impl Expr {
     fn parse_binary_operator(
         lexer: &mut Lexer<impl Iterator<Item = char>>,
         current_precedence: usize,
+        line: Option<usize>,
     ) -> Result<Self, SyntaxError> {
         if current_precedence > Op::MAX_PRECEDENCE {
-            return Self::parse_fun_or_var_or_sym(lexer);
+            return Self::parse_fun_or_var_or_sym(lexer, line);
         }
 
-        let mut result = Self::parse_binary_operator(lexer, current_precedence + 1)?;
+        let mut result = Self::parse_binary_operator(lexer, current_precedence + 1, line)?;
 
         while let Some(op) = Op::from_token_kind(lexer.peek_token().kind) {
             if current_precedence != op.precedence() {
@@ -249,7 +272,11 @@ impl Expr {
             result = Expr::Op(
                 op,
                 Box::new(result),
-                Box::new(Self::parse_binary_operator(lexer, current_precedence)?),
+                Box::new(Self::parse_binary_operator(
+                    lexer,
+                    current_precedence,
+                    line,
+                )?),
             );
         }
 
@@ -257,7 +284,221 @@ impl Expr {
     }
 
     pub fn parse(lexer: &mut Lexer<impl Iterator<Item = char>>) -> Result<Self, SyntaxError> {
-        Self::parse_binary_operator(lexer, 0)
+        Self::parse_binary_operator(lexer, 0, None)
+    }
+
+    /// Like [`Expr::parse`], but the arguments of the functions must
+    /// open on the line the expression starts on. An open paren on the
+    /// next line is left for the next command.
+    pub fn parse_line(lexer: &mut Lexer<impl Iterator<Item = char>>) -> Result<Self, SyntaxError> {
+        let line = lexer.peek_token().loc.row;
+        Self::parse_binary_operator(lexer, 0, Some(line))
+    }
+
+    /// Names of all the symbols used in the expression
+    pub fn syms(&self) -> BTreeSet<String> {
+        fn syms_impl(expr: &Expr, syms: &mut BTreeSet<String>) {
+            if let Expr::Sym(name) = expr {
+                syms.insert(name.clone());
+            }
+            for subexpr in expr.subexprs() {
+                syms_impl(subexpr, syms)
+            }
+        }
+
+        let mut syms = BTreeSet::new();
+        syms_impl(self, &mut syms);
+        syms
+    }
+
+    /// Names of all the variables used in the expression
+    pub fn vars(&self) -> BTreeSet<String> {
+        fn vars_impl(expr: &Expr, vars: &mut BTreeSet<String>) {
+            if let Expr::Var(name) = expr {
+                vars.insert(name.clone());
+            }
+            for subexpr in expr.subexprs() {
+                vars_impl(subexpr, vars)
+            }
+        }
+
+        let mut vars = BTreeSet::new();
+        vars_impl(self, &mut vars);
+        vars
+    }
+
+    /// Amount of symbols, variables, functors and operators in the expression
+    pub fn size(&self) -> usize {
+        1 + self.subexprs().iter().map(|x| x.size()).sum::<usize>()
+    }
+
+    /// Amount of levels of nesting in the expression. Symbols and
+    /// variables are one level deep.
+    pub fn depth(&self) -> usize {
+        1 + self.subexprs().iter().map(|x| x.depth()).max().unwrap_or(0)
+    }
+
+    /// Total order of the expressions used to orient equations.
+    ///
+    /// Smaller expressions go first and expressions of the same size
+    /// are compared structurally. Replacing a subexpression with a
+    /// lesser one always produces a lesser expression, so a sequence of
+    /// rewrites that only ever goes down this order is finite.
+    pub fn term_cmp(&self, other: &Expr) -> Ordering {
+        fn rank(expr: &Expr) -> usize {
+            match expr {
+                Expr::Var(_) => 0,
+                Expr::Sym(_) => 1,
+                Expr::Op(_, _, _) => 2,
+                Expr::Fun(_, _) => 3,
+            }
+        }
+
+        self.size()
+            .cmp(&other.size())
+            .then_with(|| rank(self).cmp(&rank(other)))
+            .then_with(|| match (self, other) {
+                (Expr::Var(name1), Expr::Var(name2)) | (Expr::Sym(name1), Expr::Sym(name2)) => {
+                    name1.cmp(name2)
+                }
+                (Expr::Op(op1, _, _), Expr::Op(op2, _, _)) => op1.cmp(op2),
+                (Expr::Fun(_, args1), Expr::Fun(_, args2)) => args1.len().cmp(&args2.len()),
+                _ => Ordering::Equal,
+            })
+            .then_with(|| {
+                self.subexprs()
+                    .iter()
+                    .zip(other.subexprs().iter())
+                    .map(|(a, b)| a.term_cmp(b))
+                    .find(|ordering| ordering.is_ne())
+                    .unwrap_or(Ordering::Equal)
+            })
+    }
+
+    /// Same expression with the chains of the associative and commutative
+    /// operators `ops` flattened and their operands sorted by
+    /// [`Expr::term_cmp`]. The expressions that are equal modulo
+    /// associativity and commutativity of `ops` become structurally equal.
+    pub fn ac_normalized(&self, ops: &BTreeSet<Op>) -> Self {
+        fn collect_operands(op: Op, expr: &Expr, ops: &BTreeSet<Op>, operands: &mut Vec<Expr>) {
+            match expr {
+                Expr::Op(sub_op, lhs, rhs) if *sub_op == op => {
+                    collect_operands(op, lhs, ops, operands);
+                    collect_operands(op, rhs, ops, operands);
+                }
+                _ => operands.push(expr.ac_normalized(ops)),
+            }
+        }
+
+        match self {
+            Self::Op(op, _, _) if ops.contains(op) => {
+                let mut operands = Vec::new();
+                collect_operands(*op, self, ops, &mut operands);
+                operands.sort_by(|a, b| a.term_cmp(b));
+                let mut operands = operands.into_iter();
+                let first = operands
+                    .next()
+                    .expect("Binary operator always has operands");
+                operands.fold(first, |lhs, rhs| {
+                    Self::Op(*op, Box::new(lhs), Box::new(rhs))
+                })
+            }
+            _ => self.with_subexprs(
+                self.subexprs()
+                    .into_iter()
+                    .map(|subexpr| subexpr.ac_normalized(ops))
+                    .collect(),
+            ),
+        }
+    }
+
+    /// Rough measure of how far apart two expressions are. Equal
+    /// expressions are at the distance of 0. Otherwise the expressions
+    /// are compared node by node and the mismatched subexpressions
+    /// contribute their size.
+    pub fn distance(&self, other: &Expr) -> usize {
+        if self == other {
+            return 0;
+        }
+        let same_node = match (self, other) {
+            (Expr::Op(op1, _, _), Expr::Op(op2, _, _)) => op1 == op2,
+            (Expr::Fun(_, args1), Expr::Fun(_, args2)) => args1.len() == args2.len(),
+            _ => false,
+        };
+        if same_node {
+            self.subexprs()
+                .iter()
+                .zip(other.subexprs().iter())
+                .map(|(a, b)| a.distance(b))
+                .sum()
+        } else {
+            self.size().max(other.size())
+        }
+    }
+
+    /// Direct subexpressions in left to right order: the head of a
+    /// functor followed by its arguments, or both operands of a binary
+    /// operator.
+    pub fn subexprs(&self) -> Vec<&Expr> {
+        match self {
+            Self::Sym(_) | Self::Var(_) => vec![],
+            Self::Fun(head, args) => {
+                let mut subexprs = vec![&**head];
+                subexprs.extend(args.iter());
+                subexprs
+            }
+            Self::Op(_, lhs, rhs) => vec![&**lhs, &**rhs],
+        }
+    }
+
+    /// Same expression but with the direct subexpressions replaced by
+    /// `subexprs` in the order returned by [`Expr::subexprs`].
+    pub fn with_subexprs(&self, subexprs: Vec<Expr>) -> Self {
+        let mut subexprs = subexprs.into_iter();
+        let mut next = || {
+            subexprs
+                .next()
+                .expect("Amount of subexprs must match the shape of the expression")
+        };
+        match self {
+            Self::Sym(_) | Self::Var(_) => self.clone(),
+            Self::Fun(_, args) => {
+                let head = next();
+                let args = args.iter().map(|_| next()).collect();
+                Self::Fun(Box::new(head), args)
+            }
+            Self::Op(op, _, _) => {
+                let lhs = next();
+                let rhs = next();
+                Self::Op(*op, Box::new(lhs), Box::new(rhs))
+            }
+        }
+    }
+
+    /// Subexpression located at `path`. Each element of the path is an
+    /// index into [`Expr::subexprs`] of the previous level.
+    pub fn subexpr_at_mut(&mut self, path: &[usize]) -> Option<&mut Expr> {
+        match path.split_first() {
+            None => Some(self),
+            Some((index, rest)) => {
+                let subexpr = match self {
+                    Self::Sym(_) | Self::Var(_) => None,
+                    Self::Fun(head, args) => {
+                        if *index == 0 {
+                            Some(&mut **head)
+                        } else {
+                            args.get_mut(index - 1)
+                        }
+                    }
+                    Self::Op(_, lhs, rhs) => match index {
+                        0 => Some(&mut **lhs),
+                        1 => Some(&mut **rhs),
+                        _ => None,
+                    },
+                }?;
+                subexpr.subexpr_at_mut(rest)
+            }
+        }
     }
 
     pub fn pattern_match(&self, value: &Expr) -> Option<HashMap<String, Expr>> {
@@ -285,16 +526,12 @@ impl Expr {
                         && pattern_match_impl(rhs1, rhs2, bindings)
                 }
                 (Fun(name1, args1), Fun(name2, args2)) => {
-                    if pattern_match_impl(name1, name2, bindings) && args1.len() == args2.len() {
-                        for i in 0..args1.len() {
-                            if !pattern_match_impl(&args1[i], &args2[i], bindings) {
-                                return false;
-                            }
-                        }
-                        true
-                    } else {
-                        false
-                    }
+                    pattern_match_impl(name1, name2, bindings)
+                        && args1.len() == args2.len()
+                        && args1
+                            .iter()
+                            .zip(args2.iter())
+                            .all(|(arg1, arg2)| pattern_match_impl(arg1, arg2, bindings))
                 }
                 _ => false,
             }
@@ -308,6 +545,136 @@ impl Expr {
             None
         }
     }
+
+    /// Most general bindings of the variables of both expressions that
+    /// make them equal. Unlike [`Expr::pattern_match`] the variables of
+    /// `other` are bound too. The values of the bindings don't refer to
+    /// the bound variables.
+    pub fn unify(&self, other: &Expr) -> Option<HashMap<String, Expr>> {
+        fn resolve(expr: &Expr, bindings: &HashMap<String, Expr>) -> Expr {
+            match expr {
+                Expr::Var(name) => match bindings.get(name) {
+                    Some(value) => resolve(value, bindings),
+                    None => expr.clone(),
+                },
+                _ => expr.with_subexprs(
+                    expr.subexprs()
+                        .into_iter()
+                        .map(|subexpr| resolve(subexpr, bindings))
+                        .collect(),
+                ),
+            }
+        }
+
+        fn unify_impl(a: &Expr, b: &Expr, bindings: &mut HashMap<String, Expr>) -> bool {
+            use Expr::*;
+            let a = resolve(a, bindings);
+            let b = resolve(b, bindings);
+            match (&a, &b) {
+                (Var(name), _) | (_, Var(name)) if name == "_" => true,
+                (Var(name1), Var(name2)) if name1 == name2 => true,
+                (Var(name), value) | (value, Var(name)) => {
+                    if value.vars().contains(name) {
+                        false
+                    } else {
+                        bindings.insert(name.clone(), value.clone());
+                        true
+                    }
+                }
+                (Sym(name1), Sym(name2)) => name1 == name2,
+                (Op(op1, lhs1, rhs1), Op(op2, lhs2, rhs2)) => {
+                    *op1 == *op2
+                        && unify_impl(lhs1, lhs2, bindings)
+                        && unify_impl(rhs1, rhs2, bindings)
+                }
+                (Fun(name1, args1), Fun(name2, args2)) => {
+                    args1.len() == args2.len()
+                        && unify_impl(name1, name2, bindings)
+                        && args1
+                            .iter()
+                            .zip(args2.iter())
+                            .all(|(arg1, arg2)| unify_impl(arg1, arg2, bindings))
+                }
+                _ => false,
+            }
+        }
+
+        let mut bindings = HashMap::new();
+        if !unify_impl(self, other, &mut bindings) {
+            return None;
+        }
+        Some(
+            bindings
+                .iter()
+                .map(|(name, value)| (name.clone(), resolve(value, &bindings)))
+                .collect(),
+        )
+    }
+
+    /// The least general generalization of the expressions: the most
+    /// specific pattern that matches both of them. The subexpressions
+    /// that differ become fresh variables, and the same pair of differing
+    /// subexpressions always becomes the same variable.
+    pub fn anti_unify(&self, other: &Expr) -> Expr {
+        struct Generalization {
+            taken: BTreeSet<String>,
+            vars: HashMap<(Expr, Expr), Expr>,
+        }
+
+        impl Generalization {
+            fn fresh_var(&mut self) -> Expr {
+                let name = (0..)
+                    .map(|index: usize| {
+                        let letter = char::from(b'A' + (index % 26) as u8);
+                        match index / 26 {
+                            0 => letter.to_string(),
+                            suffix => format!("{}_{}", letter, suffix),
+                        }
+                    })
+                    .find(|name| !self.taken.contains(name))
+                    .expect("There are infinitely many candidates");
+                self.taken.insert(name.clone());
+                Expr::Var(name)
+            }
+
+            fn anti_unify(&mut self, a: &Expr, b: &Expr) -> Expr {
+                use Expr::*;
+                match (a, b) {
+                    _ if a == b => a.clone(),
+                    (Op(op1, lhs1, rhs1), Op(op2, lhs2, rhs2)) if op1 == op2 => Op(
+                        *op1,
+                        Box::new(self.anti_unify(lhs1, lhs2)),
+                        Box::new(self.anti_unify(rhs1, rhs2)),
+                    ),
+                    (Fun(head1, args1), Fun(head2, args2)) if args1.len() == args2.len() => Fun(
+                        Box::new(self.anti_unify(head1, head2)),
+                        args1
+                            .iter()
+                            .zip(args2.iter())
+                            .map(|(arg1, arg2)| self.anti_unify(arg1, arg2))
+                            .collect(),
+                    ),
+                    _ => {
+                        let key = (a.clone(), b.clone());
+                        if let Some(var) = self.vars.get(&key) {
+                            return var.clone();
+                        }
+                        let var = self.fresh_var();
+                        self.vars.insert(key, var.clone());
+                        var
+                    }
+                }
+            }
+        }
+
+        let mut taken = self.vars();
+        taken.extend(other.vars());
+        let mut generalization = Generalization {
+            taken,
+            vars: HashMap::new(),
+        };
+        generalization.anti_unify(self, other)
+    }
 }
 
 #[allow(unused_macros)]
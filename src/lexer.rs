@@ -1,3 +1,4 @@
+use std::collections::VecDeque;
 use std::fmt;
 use std::iter::Peekable;
 
@@ -43,13 +44,19 @@ pub enum TokenKind {
     OpenParen,
     CloseParen,
     Comma,
+    Semicolon,
     Equals,
     Colon,
     DoubleColon,
     OpenCurly,
     CloseCurly,
+    OpenBracket,
+    CloseBracket,
+    ColonEquals,
     Bar,
     Bang,
+    At,
+    Question,
 
     // Binary Operators
     Plus,
@@ -92,7 +99,11 @@ impl fmt::Display for TokenKind {
             CloseParen => write!(f, "close paren"),
             OpenCurly => write!(f, "open curly"),
             CloseCurly => write!(f, "close curly"),
+            OpenBracket => write!(f, "open bracket"),
+            CloseBracket => write!(f, "close bracket"),
+            ColonEquals => write!(f, "colon equals"),
             Comma => write!(f, "comma"),
+            Semicolon => write!(f, "semicolon"),
             Equals => write!(f, "equals"),
             EqualsEquals => write!(f, "double equals"),
             Colon => write!(f, "colon"),
@@ -107,6 +118,8 @@ impl fmt::Display for TokenKind {
             Caret => write!(f, "caret"),
             Bar => write!(f, "bar"),
             Bang => write!(f, "bang"),
+            At => write!(f, "at"),
+            Question => write!(f, "question mark"),
             End => write!(f, "end of input"),
         }
     }
@@ -131,7 +144,7 @@ impl fmt::Display for Token {
 
 pub struct Lexer<Chars: Iterator<Item = char>> {
     chars: Peekable<Chars>,
-    peeked: Option<Token>,
+    peeked: VecDeque<Token>,
     exhausted: bool,
     file_path: Option<String>,
     lnum: usize,
@@ -143,7 +156,7 @@ impl<Chars: Iterator<Item = char>> Lexer<Chars> {
     pub fn new(chars: Chars, file_path: Option<String>) -> Self {
         Self {
             chars: chars.peekable(),
-            peeked: None,
+            peeked: VecDeque::new(),
             exhausted: false,
             file_path,
             lnum: 0,
@@ -170,13 +183,31 @@ impl<Chars: Iterator<Item = char>> Lexer<Chars> {
     }
 
     pub fn peek_token(&mut self) -> &Token {
-        let token = self.next_token();
-        self.peeked.insert(token)
+        if self.peeked.is_empty() {
+            let token = self.chop_tokens_from_chars();
+            self.peeked.push_back(token);
+        }
+        &self.peeked[0]
+    }
+
+    /// The token after the one returned by [`Lexer::peek_token`]. The
+    /// terminator is its own next token.
+    pub fn peek_next_token(&mut self) -> &Token {
+        self.peek_token();
+        if self.peeked.len() < 2 {
+            let token = if self.exhausted {
+                self.peeked[0].clone()
+            } else {
+                self.chop_tokens_from_chars()
+            };
+            self.peeked.push_back(token);
+        }
+        &self.peeked[1]
     }
 
     pub fn next_token(&mut self) -> Token {
         self.peeked
-            .take()
+            .pop_front()
             .unwrap_or_else(|| self.chop_tokens_from_chars())
     }
 
@@ -235,6 +266,11 @@ impl<Chars: Iterator<Item = char>> Lexer<Chars> {
                         text,
                         loc,
                     },
+                    ';' => Token {
+                        kind: TokenKind::Semicolon,
+                        text,
+                        loc,
+                    },
                     '=' => {
                         if self.chars.next_if(|x| *x == '=').is_some() {
                             self.cnum += 1;
@@ -261,6 +297,14 @@ impl<Chars: Iterator<Item = char>> Lexer<Chars> {
                                 text,
                                 loc,
                             }
+                        } else if self.chars.next_if(|x| *x == '=').is_some() {
+                            self.cnum += 1;
+                            text.push('=');
+                            Token {
+                                kind: TokenKind::ColonEquals,
+                                text,
+                                loc,
+                            }
                         } else {
                             Token {
                                 kind: TokenKind::Colon,
@@ -309,6 +353,16 @@ impl<Chars: Iterator<Item = char>> Lexer<Chars> {
                         text,
                         loc,
                     },
+                    '[' => Token {
+                        kind: TokenKind::OpenBracket,
+                        text,
+                        loc,
+                    },
+                    ']' => Token {
+                        kind: TokenKind::CloseBracket,
+                        text,
+                        loc,
+                    },
                     '|' => Token {
                         kind: TokenKind::Bar,
                         text,
@@ -319,6 +373,16 @@ impl<Chars: Iterator<Item = char>> Lexer<Chars> {
                         text,
                         loc,
                     },
+                    '@' => Token {
+                        kind: TokenKind::At,
+                        text,
+                        loc,
+                    },
+                    '?' => Token {
+                        kind: TokenKind::Question,
+                        text,
+                        loc,
+                    },
                     '"' => {
                         // TODO: no support for escaped sequences inside of string literals
                         text.clear();
@@ -380,7 +444,7 @@ impl<Chars: Iterator<Item = char>> Iterator for Lexer<Chars> {
     type Item = Token;
 
     fn next(&mut self) -> Option<Self::Item> {
-        if self.exhausted {
+        if self.exhausted && self.peeked.is_empty() {
             None
         } else {
             Some(self.next_token())
@@ -1,10 +1,11 @@
-use std::collections::HashMap;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::env;
 use std::fmt;
 use std::fs;
 use std::io;
 use std::io::Write;
 use std::io::{stdin, stdout};
+use std::path::{Path, PathBuf};
 
 use termion::event::Key;
 use termion::input::TermRead;
@@ -25,6 +26,42 @@ enum CommandSyntaxError {
     LoadArg(Token),
     SaveArg(Token),
     DeleteArg(Token),
+    ShowArg(Token),
+    MatchesArg(Token),
+    ModuleName(Token),
+    /// Only `ac` theory is supported
+    TheoryName(Token),
+    TheoryOperator(Token),
+    /// The expression in front of the colon is not a side of the equation
+    SideName(Token),
+    /// Only the rule applications can target a side of the equation
+    SideCommand(Token),
+    /// The path of the focus is not a dot separated list of numbers
+    FocusPath(Token),
+    FocusWithoutShaping(Token),
+    CasesSubject(expr::SyntaxError),
+    /// The cases are not introduced with `of`
+    CasesOf(Token),
+    CasePattern(expr::SyntaxError),
+    /// The type of the induction is not introduced with `on`
+    InductionOn(Token),
+    InductionData(Token),
+    InductionSubject(expr::SyntaxError),
+    GeneralizeExpr(expr::SyntaxError),
+    GeneralizeSep(Token),
+    DataName(Token),
+    DataSep(Token),
+    DataConstructor(expr::SyntaxError),
+    /// The constructor is neither a symbol nor a symbol applied to the
+    /// type names
+    DataConstructorShape(Token, Expr),
+    LemmaName(Token),
+    LemmaEquation(expr::SyntaxError),
+    /// The statement of the lemma is not an equation
+    LemmaNotEquation(Token),
+    LemmaWithoutShaping(Token),
+    ShapingTarget(expr::SyntaxError),
+    RulesPattern(expr::SyntaxError),
     /// Failed to parse the start of the command.
     ///
     /// The start of the command is always an expression. The specific
@@ -36,11 +73,54 @@ enum CommandSyntaxError {
     /// the command and determines the command itself.
     CommandSep(Token),
     StrategyName(Token),
+    /// Order or direction of the traversal was already specified
+    StrategyModifier(Token),
     AnonymousRuleBody(expr::SyntaxError),
     AnonymousRuleWithoutStrategy(Token),
     DefineRuleHead(expr::SyntaxError),
     DefineRuleBody(expr::SyntaxError),
     DefineRuleSep(Token),
+    /// `@` is not followed by the name of the tag
+    TagName(Token),
+    /// Tags are only allowed in front of the rule definitions
+    TaggedCommand(Token),
+    /// `redefine` is only allowed in front of the rule definitions
+    RedefinedCommand(Token),
+    /// `assume` is only allowed in front of the plain rule definitions
+    AssumedCommand(Token),
+    /// The bound variable is not a variable
+    BindingName(Token),
+    BindingSep(Token),
+    BindingValue(expr::SyntaxError),
+    BindingsEnd(Token),
+    /// Variables can only be bound for the rule applications
+    BoundCommand(Token),
+    /// The applied rule is neither a name nor a name with arguments
+    AppliedRuleName(Token, Expr),
+    /// Parameters of the rule must be variables
+    RuleParam(Token, Expr),
+    /// Parameterized rules can't be defined via shaping
+    ParameterizedShaping(Token),
+    /// The symbol after `forall` is not a lowercase name
+    ForallName(Token),
+    ForallSep(Token),
+    /// `forall` is only allowed for the rules defined via shaping
+    ForallWithoutShaping(Token),
+    /// The symbol after `forall` does not appear in the head of the rule
+    ForallNotInHead(Token, Expr),
+    DerivationStep(expr::SyntaxError),
+    /// The step of the derivation is not a rule
+    DerivedRule(Token, Expr),
+    ProveEquation(expr::SyntaxError),
+    /// The statement of the `prove` command is not an equation
+    ProveNotEquation(Token),
+    ProveRuleName(Token),
+    ProveDepth(Token),
+    LimitName(Token),
+    LimitValue(Token),
+    UnboundValue(Token),
+    /// Amount of repetitions of `undo` or `redo` is not a positive number
+    RepeatCount(Token),
     UnparsedInput(Token),
 }
 
@@ -50,16 +130,70 @@ impl CommandSyntaxError {
             Self::LoadArg(token)
             | Self::SaveArg(token)
             | Self::DeleteArg(token)
+            | Self::ShowArg(token)
+            | Self::MatchesArg(token)
+            | Self::ModuleName(token)
+            | Self::TheoryName(token)
+            | Self::TheoryOperator(token)
+            | Self::SideName(token)
+            | Self::SideCommand(token)
+            | Self::FocusPath(token)
+            | Self::FocusWithoutShaping(token)
+            | Self::CasesOf(token)
+            | Self::InductionOn(token)
+            | Self::InductionData(token)
+            | Self::GeneralizeSep(token)
+            | Self::DataName(token)
+            | Self::DataSep(token)
+            | Self::DataConstructorShape(token, _)
+            | Self::LemmaName(token)
+            | Self::LemmaNotEquation(token)
+            | Self::LemmaWithoutShaping(token)
             | Self::CommandSep(token)
             | Self::StrategyName(token)
+            | Self::StrategyModifier(token)
             | Self::AnonymousRuleWithoutStrategy(token)
             | Self::UnparsedInput(token)
-            | Self::DefineRuleSep(token) => &token.loc,
+            | Self::DefineRuleSep(token)
+            | Self::TagName(token)
+            | Self::TaggedCommand(token)
+            | Self::RedefinedCommand(token)
+            | Self::AssumedCommand(token)
+            | Self::BindingName(token)
+            | Self::BindingSep(token)
+            | Self::BindingsEnd(token)
+            | Self::BoundCommand(token)
+            | Self::AppliedRuleName(token, _)
+            | Self::RuleParam(token, _)
+            | Self::ParameterizedShaping(token)
+            | Self::ForallName(token)
+            | Self::ForallSep(token)
+            | Self::ForallWithoutShaping(token)
+            | Self::ForallNotInHead(token, _)
+            | Self::DerivedRule(token, _)
+            | Self::ProveNotEquation(token)
+            | Self::ProveRuleName(token)
+            | Self::ProveDepth(token)
+            | Self::LimitName(token)
+            | Self::LimitValue(token)
+            | Self::UnboundValue(token)
+            | Self::RepeatCount(token) => &token.loc,
 
             Self::CommandStart(expr_err)
             | Self::AnonymousRuleBody(expr_err)
             | Self::DefineRuleHead(expr_err)
-            | Self::DefineRuleBody(expr_err) => expr_err.loc(),
+            | Self::DefineRuleBody(expr_err)
+            | Self::ProveEquation(expr_err)
+            | Self::RulesPattern(expr_err)
+            | Self::LemmaEquation(expr_err)
+            | Self::ShapingTarget(expr_err)
+            | Self::BindingValue(expr_err)
+            | Self::CasesSubject(expr_err)
+            | Self::CasePattern(expr_err)
+            | Self::InductionSubject(expr_err)
+            | Self::DataConstructor(expr_err)
+            | Self::GeneralizeExpr(expr_err)
+            | Self::DerivationStep(expr_err) => expr_err.loc(),
         }
     }
 }
@@ -85,6 +219,70 @@ impl fmt::Display for CommandSyntaxError {
                 TokenKind::Ident,
                 token
             ),
+            Self::ShowArg(token) => write!(
+                f,
+                "`show` Command Argument must be {}, but got {} instead",
+                TokenKind::Ident,
+                token
+            ),
+            Self::MatchesArg(token) => write!(
+                f,
+                "`matches` Command Argument must be {}, but got {} instead",
+                TokenKind::Ident,
+                token
+            ),
+            Self::ModuleName(token) => write!(
+                f,
+                "Module Name must be {}, but got {} instead",
+                TokenKind::Ident,
+                token
+            ),
+            Self::TheoryName(token) => write!(
+                f,
+                "only `ac` Theory is supported, but got {} instead",
+                token
+            ),
+            Self::TheoryOperator(token) => write!(
+                f,
+                "Theory Operator must be a binary operator, but got {} instead",
+                token
+            ),
+            Self::SideName(_token) => write!(
+                f,
+                "only `left`, `right` or `both` can stand in front of {}",
+                TokenKind::Colon
+            ),
+            Self::SideCommand(_token) => write!(
+                f,
+                "only Rule Applications can target a side of the equation"
+            ),
+            Self::FocusPath(token) => write!(
+                f,
+                "Focus Path must be numbers separated by dots like `1.0`, but got {} instead",
+                token
+            ),
+            Self::FocusWithoutShaping(token) => write!(
+                f,
+                "expected {} after the Focus Path, but got {} instead",
+                TokenKind::OpenCurly,
+                token
+            ),
+            Self::LemmaName(token) => write!(
+                f,
+                "Lemma Name must be {}, but got {} instead",
+                TokenKind::Ident,
+                token
+            ),
+            Self::LemmaNotEquation(_token) => write!(
+                f,
+                "the statement of the Lemma must be an equation `<head> == <target>`"
+            ),
+            Self::LemmaWithoutShaping(token) => write!(
+                f,
+                "expected {} after the statement of the Lemma, but got {} instead",
+                TokenKind::OpenCurly,
+                token
+            ),
             // TODO: report what are the valid command separators
             Self::CommandSep(token) => {
                 write!(f, "expected Command Separator, but got {} instead", token)
@@ -95,16 +293,181 @@ impl fmt::Display for CommandSyntaxError {
                 TokenKind::Ident,
                 token
             ),
+            Self::StrategyModifier(token) => write!(
+                f,
+                "conflicting Strategy Modifier {}, the traversal was already specified",
+                token
+            ),
             Self::AnonymousRuleWithoutStrategy(token) => write!(
                 f,
-                "expected {} after the Anonymous Rule, but got {}",
+                "expected {} or {} after the Anonymous Rule, but got {}",
                 TokenKind::Bar,
+                TokenKind::Question,
                 token.kind
             ),
             // TODO: report what are the valid rule definition separators
             Self::DefineRuleSep(token) => {
                 write!(f, "unexpected Rule Definition Separator {}", token)
             }
+            Self::TagName(token) => write!(
+                f,
+                "Tag Name must be {}, but got {} instead",
+                TokenKind::Ident,
+                token
+            ),
+            Self::TaggedCommand(_token) => write!(f, "only Rule Definitions can be tagged"),
+            Self::RedefinedCommand(_token) => {
+                write!(f, "only Rule Definitions can be redefined")
+            }
+            Self::BindingName(token) => write!(
+                f,
+                "Bound Variable must be a variable name, but got {} instead",
+                token
+            ),
+            Self::BindingSep(token) => write!(
+                f,
+                "expected {} after the Bound Variable, but got {} instead",
+                TokenKind::ColonEquals,
+                token
+            ),
+            Self::BindingsEnd(token) => write!(
+                f,
+                "expected {} or {} after the Binding, but got {} instead",
+                TokenKind::Comma,
+                TokenKind::CloseBracket,
+                token
+            ),
+            Self::BoundCommand(token) => write!(
+                f,
+                "expected {} or {} after the Bindings, but got {} instead",
+                TokenKind::Bar,
+                TokenKind::Question,
+                token
+            ),
+            Self::AppliedRuleName(_token, expr) => write!(
+                f,
+                "Applied Rule must be a rule name, optionally with arguments, but got {} {} instead",
+                expr.human_name(),
+                expr
+            ),
+            Self::RuleParam(_token, expr) => write!(
+                f,
+                "Rule Parameter must be a variable, but got {} {} instead",
+                expr.human_name(),
+                expr
+            ),
+            Self::ParameterizedShaping(_token) => write!(
+                f,
+                "parameterized rules can't be defined via shaping"
+            ),
+            Self::DerivedRule(_token, expr) => write!(
+                f,
+                "Step of the Derivation must be a rule name, `sym(<rule>)` or a rule with arguments, but got {} {} instead",
+                expr.human_name(),
+                expr
+            ),
+            Self::AssumedCommand(_token) => write!(
+                f,
+                "only Rule Definitions without tags and `redefine` can be assumed"
+            ),
+            Self::ProveNotEquation(_token) => write!(
+                f,
+                "`prove` Command Argument must be an equation `<start> == <goal>`"
+            ),
+            Self::ProveRuleName(token) => write!(
+                f,
+                "Rule Name after `using` must be {}, but got {} instead",
+                TokenKind::Ident,
+                token
+            ),
+            Self::ProveDepth(token) => write!(
+                f,
+                "`depth` must be a non-negative number, but got {} instead",
+                token
+            ),
+            Self::LimitName(token) => write!(
+                f,
+                "`limit` Command expects `nodes`, `depth` or `steps`, but got {} instead",
+                token
+            ),
+            Self::LimitValue(token) => write!(
+                f,
+                "Limit Value must be a positive number, but got {} instead",
+                token
+            ),
+            Self::CasesOf(token) => write!(
+                f,
+                "expected `of` after the Subject of the `cases` Command, but got {} instead",
+                token
+            ),
+            Self::InductionOn(token) => write!(
+                f,
+                "expected `on` after the Subject of the `induction` Command, but got {} instead",
+                token
+            ),
+            Self::InductionData(token) => write!(
+                f,
+                "Data Type of the `induction` Command must be {}, but got {} instead",
+                TokenKind::Ident,
+                token
+            ),
+            Self::GeneralizeSep(token) => write!(
+                f,
+                "expected {} between the Expressions of the `generalize` Command, but got {} instead",
+                TokenKind::Comma,
+                token
+            ),
+            Self::DataName(token) => write!(
+                f,
+                "Name of the Data Type must be {}, but got {} instead",
+                TokenKind::Ident,
+                token
+            ),
+            Self::DataSep(token) => write!(
+                f,
+                "expected {} after the Name of the Data Type, but got {} instead",
+                TokenKind::Equals,
+                token
+            ),
+            Self::DataConstructorShape(_token, expr) => write!(
+                f,
+                "Constructor of the Data Type must be a symbol or a symbol applied to the type names, but got {} {}",
+                expr.human_name(),
+                expr
+            ),
+            Self::ForallName(token) => write!(
+                f,
+                "Symbol after `forall` must be a lowercase {}, but got {} instead",
+                TokenKind::Ident,
+                token
+            ),
+            Self::ForallSep(token) => write!(
+                f,
+                "expected {} between the Symbols of `forall` or a dot after the last one, but got {} instead",
+                TokenKind::Comma,
+                token
+            ),
+            Self::ForallWithoutShaping(token) => write!(
+                f,
+                "`forall` is only allowed for the rules defined via shaping, expected {}, but got {} instead",
+                TokenKind::OpenCurly,
+                token
+            ),
+            Self::ForallNotInHead(token, head) => write!(
+                f,
+                "Symbol `{}` after `forall` does not appear in the head {}",
+                token.text, head
+            ),
+            Self::UnboundValue(token) => write!(
+                f,
+                "`unbound` Command expects `error`, `warn` or `fresh`, but got {} instead",
+                token
+            ),
+            Self::RepeatCount(token) => write!(
+                f,
+                "Amount of Repetitions must be a positive number, but got {} instead",
+                token
+            ),
 
             Self::UnparsedInput(token) => {
                 write!(f, "unexpected token {} after the End of the Command", token)
@@ -123,6 +486,39 @@ impl fmt::Display for CommandSyntaxError {
             Self::DefineRuleBody(expr_err) => {
                 write!(f, "invalid Body of the Rule Definition: {}", expr_err)
             }
+            Self::ProveEquation(expr_err) => {
+                write!(f, "invalid Equation of the `prove` Command: {}", expr_err)
+            }
+            Self::RulesPattern(expr_err) => {
+                write!(f, "invalid Pattern of the `rules` Command: {}", expr_err)
+            }
+            Self::LemmaEquation(expr_err) => {
+                write!(f, "invalid Statement of the Lemma: {}", expr_err)
+            }
+            Self::ShapingTarget(expr_err) => {
+                write!(f, "invalid Target of the Shaping: {}", expr_err)
+            }
+            Self::BindingValue(expr_err) => {
+                write!(f, "invalid Value of the Binding: {}", expr_err)
+            }
+            Self::DerivationStep(expr_err) => {
+                write!(f, "invalid Step of the Derivation: {}", expr_err)
+            }
+            Self::CasesSubject(expr_err) => {
+                write!(f, "invalid Subject of the `cases` Command: {}", expr_err)
+            }
+            Self::CasePattern(expr_err) => {
+                write!(f, "invalid Case of the `cases` Command: {}", expr_err)
+            }
+            Self::InductionSubject(expr_err) => {
+                write!(f, "invalid Subject of the `induction` Command: {}", expr_err)
+            }
+            Self::GeneralizeExpr(expr_err) => {
+                write!(f, "invalid Expression of the `generalize` Command: {}", expr_err)
+            }
+            Self::DataConstructor(expr_err) => {
+                write!(f, "invalid Constructor of the Data Type: {}", expr_err)
+            }
         }
     }
 }
@@ -132,6 +528,13 @@ impl fmt::Display for CommandSyntaxError {
 enum RuntimeError {
     RuleAlreadyExists(String, Loc, Option<Loc>),
     RuleDoesNotExist(String, Loc),
+    /// Built-in rules can't be redefined or deleted
+    BuiltinRule(String, Loc),
+    /// The name refers to the rules of several modules
+    AmbiguousRule(String, Vec<String>, Loc),
+    UnknownModule(String, Loc),
+    /// The path of the focus leads outside of the expression
+    NoSubexpression(Vec<usize>, Expr, Loc),
     NoShapingInPlace(Loc),
     EndOfHistory(Loc),
     UnknownStrategy(String, Loc),
@@ -140,6 +543,63 @@ enum RuntimeError {
     NoMatch(Loc),
     CouldNotLoadFile(Loc, io::Error),
     CouldNotSaveFile(Loc, io::Error),
+    /// The goal could not be reached within the given depth
+    ProofNotFound(usize, Loc),
+    /// The rule argument of `apply_rule` does not describe a rule
+    NotARule(Expr, Loc),
+    /// Rewriting went beyond the configured limit
+    LimitExceeded(Limit, usize, Loc),
+    /// The chain of the files that load each other in a cycle
+    LoadCycle(Vec<PathBuf>, Loc),
+    /// The shaping is finished with an expression other than its target
+    TargetNotReached {
+        expected: Expr,
+        actual: Expr,
+        loc: Loc,
+    },
+    /// The command targets a side of an expression that is not an equation
+    NotAnEquation(Expr, Loc),
+    /// The rule is defined from an equation whose sides are not equal
+    EquationNotClosed(Expr, Loc),
+    /// The bound variable does not appear in the rule
+    UnknownVariable(String, Loc),
+    /// Variables of the rule body that get no value from the match
+    UnboundVariables(Vec<String>, Loc),
+    /// Variables of the body of the defined rule that don't appear in
+    /// its head
+    UnboundInDefinition(Vec<String>, Loc),
+    /// The result of the first rule of the derivation never matches the
+    /// second one
+    RulesDontCompose {
+        body: Expr,
+        head: Expr,
+        loc: Loc,
+    },
+    /// Only equations can be split into cases
+    CasesNotEquation(Expr, Loc),
+    /// The subject of the cases does not appear in the goal
+    CaseNotInGoal(Expr, Expr, Loc),
+    /// The variable of the case is already used by the goal
+    CaseVariableTaken(String, Loc),
+    /// No data type has the constructor the case is made of
+    UnknownConstructor(Expr, Loc),
+    /// The cases miss some values of the subject, like the given one
+    CasesNotExhaustive(Expr, Loc),
+    /// The shaping is finished while some of its goals are not shaped yet
+    OpenGoals(usize, Loc),
+    /// The name is kept for the induction hypothesis of some goal
+    HypothesisName(String, Loc),
+    UnknownData(String, Loc),
+    DataAlreadyExists(String, Loc),
+    /// The data type has several constructors with the same head
+    DuplicateConstructor(Expr, Loc),
+    /// The rule is applied with a wrong amount of arguments
+    RuleArity {
+        name: String,
+        expected: usize,
+        actual: usize,
+        loc: Loc,
+    },
 }
 
 impl fmt::Display for RuntimeError {
@@ -150,6 +610,23 @@ impl fmt::Display for RuntimeError {
             }
             Self::NoShapingInPlace(_loc) => write!(f, "no shaping in place."),
             Self::RuleDoesNotExist(name, _loc) => write!(f, "rule {} does not exist", name),
+            Self::BuiltinRule(name, _loc) => {
+                write!(f, "rule {} is built-in and can't be changed", name)
+            }
+            Self::AmbiguousRule(name, candidates, _loc) => write!(
+                f,
+                "rule name {} is ambiguous, it may refer to {}",
+                name,
+                candidates.join(", ")
+            ),
+            Self::UnknownModule(name, _loc) => write!(f, "module {} does not exist", name),
+            Self::NoSubexpression(path, expr, _loc) => write!(
+                f,
+                "{} {} has no subexpression at {}",
+                expr.human_name(),
+                expr,
+                format_path(path)
+            ),
             Self::EndOfHistory(_loc) => write!(f, "end of history"),
             Self::UnknownStrategy(name, _loc) => {
                 write!(f, "unknown rule application strategy '{}'", name)
@@ -157,13 +634,125 @@ impl fmt::Display for RuntimeError {
             Self::IrreversibleRule(_loc) => write!(f, "irreversible rule"),
             Self::StrategyIsNotSym(expr, _loc) => write!(
                 f,
-                "strategy must be a symbol but got {} {}",
+                "strategy must be a symbol or a symbol applied to the traversal modifiers, but got {} {}",
                 expr.human_name(),
                 &expr
             ),
             Self::NoMatch(_loc) => write!(f, "no match found"),
             Self::CouldNotLoadFile(_loc, err) => write!(f, "could not load file {:?}", err),
             Self::CouldNotSaveFile(_loc, err) => write!(f, "could not save file {:?}", err),
+            Self::ProofNotFound(depth, _loc) => {
+                write!(f, "could not reach the goal within {} steps", depth)
+            }
+            Self::NotARule(expr, _loc) => write!(
+                f,
+                "expected a rule name or `sym(<rule>)`, but got {} {}",
+                expr.human_name(),
+                expr
+            ),
+            Self::LimitExceeded(limit, value, _loc) => {
+                match limit {
+                    Limit::Nodes => write!(f, "expression grew beyond {} nodes", value)?,
+                    Limit::Depth => write!(f, "expression grew deeper than {} levels", value)?,
+                    Limit::Steps => write!(f, "command made more than {} rewrites", value)?,
+                }
+                write!(
+                    f,
+                    ". The limit can be changed with `limit {} <value>`",
+                    limit.name()
+                )
+            }
+            Self::LoadCycle(chain, _loc) => {
+                let chain: Vec<String> = chain
+                    .iter()
+                    .map(|path| path.display().to_string())
+                    .collect();
+                write!(f, "files load each other in a cycle: {}", chain.join(" -> "))
+            }
+            Self::TargetNotReached {
+                expected, actual, ..
+            } => write!(
+                f,
+                "shaping did not reach its target\n  expected: {}\n  actual:   {}",
+                expected, actual
+            ),
+            Self::NotAnEquation(expr, _loc) => write!(
+                f,
+                "only equations have sides, but got {} {}",
+                expr.human_name(),
+                expr
+            ),
+            Self::EquationNotClosed(expr, _loc) => {
+                write!(f, "the sides of the equation {} are not equal", expr)
+            }
+            Self::RuleArity {
+                name,
+                expected,
+                actual,
+                ..
+            } => write!(
+                f,
+                "rule {} takes {} argument(s), but got {}",
+                name, expected, actual
+            ),
+            Self::RulesDontCompose { body, head, .. } => write!(
+                f,
+                "the rules don't compose: the result {} of the first rule never matches the head {} of the second one",
+                body, head
+            ),
+            Self::UnknownVariable(name, _loc) => {
+                write!(f, "the rule has no variable {}", name)
+            }
+            Self::CasesNotEquation(expr, _loc) => write!(
+                f,
+                "only equations can be split into cases, but got {} {}",
+                expr.human_name(),
+                expr
+            ),
+            Self::CaseNotInGoal(subject, goal, _loc) => {
+                write!(f, "{} does not appear in the goal {}", subject, goal)
+            }
+            Self::CaseVariableTaken(name, _loc) => {
+                write!(f, "variable {} of the case is already used by the goal", name)
+            }
+            Self::UnknownConstructor(case, _loc) => {
+                write!(f, "no data type has a constructor for the case {}", case)
+            }
+            Self::CasesNotExhaustive(missing, _loc) => {
+                write!(f, "the cases are not exhaustive, {} is not covered", missing)
+            }
+            Self::HypothesisName(name, _loc) => write!(
+                f,
+                "name {} is reserved for an induction hypothesis of the shaping",
+                name
+            ),
+            Self::UnknownData(name, _loc) => write!(f, "data type {} does not exist", name),
+            Self::DuplicateConstructor(head, _loc) => {
+                write!(f, "constructor {} is declared more than once", head)
+            }
+            Self::DataAlreadyExists(name, _loc) => {
+                write!(f, "data type {} is already declared", name)
+            }
+            Self::OpenGoals(count, _loc) => {
+                write!(f, "{} goal(s) of the shaping are not closed yet", count)
+            }
+            Self::UnboundVariables(names, _loc) => write!(
+                f,
+                "{} {} of the rule body {} not bound by the match, bind {} like `rule[{} := ...]`",
+                if names.len() == 1 { "variable" } else { "variables" },
+                names.join(", "),
+                if names.len() == 1 { "is" } else { "are" },
+                if names.len() == 1 { "it" } else { "them" },
+                names[0]
+            ),
+            Self::UnboundInDefinition(names, _loc) => write!(
+                f,
+                "{} {} of the rule body {} not appear in the head, allow {} with `unbound warn` or `unbound fresh`",
+                if names.len() == 1 { "variable" } else { "variables" },
+                names.join(", "),
+                if names.len() == 1 { "does" } else { "do" },
+                if names.len() == 1 { "it" } else { "them" },
+            ),
         }
     }
 }
@@ -173,6 +762,10 @@ impl RuntimeError {
         match self {
             Self::RuleAlreadyExists(_, loc, _)
             | Self::RuleDoesNotExist(_, loc)
+            | Self::BuiltinRule(_, loc)
+            | Self::AmbiguousRule(_, _, loc)
+            | Self::UnknownModule(_, loc)
+            | Self::NoSubexpression(_, _, loc)
             | Self::NoShapingInPlace(loc)
             | Self::EndOfHistory(loc)
             | Self::UnknownStrategy(_, loc)
@@ -180,9 +773,46 @@ impl RuntimeError {
             | Self::StrategyIsNotSym(_, loc)
             | Self::NoMatch(loc)
             | Self::CouldNotLoadFile(loc, _)
-            | Self::CouldNotSaveFile(loc, _) => loc,
+            | Self::CouldNotSaveFile(loc, _)
+            | Self::ProofNotFound(_, loc)
+            | Self::NotARule(_, loc)
+            | Self::LimitExceeded(_, _, loc)
+            | Self::LoadCycle(_, loc)
+            | Self::NotAnEquation(_, loc)
+            | Self::EquationNotClosed(_, loc)
+            | Self::UnknownVariable(_, loc)
+            | Self::UnboundVariables(_, loc)
+            | Self::UnboundInDefinition(_, loc)
+            | Self::CasesNotEquation(_, loc)
+            | Self::CaseNotInGoal(_, _, loc)
+            | Self::CaseVariableTaken(_, loc)
+            | Self::UnknownConstructor(_, loc)
+            | Self::HypothesisName(_, loc)
+            | Self::CasesNotExhaustive(_, loc)
+            | Self::OpenGoals(_, loc)
+            | Self::UnknownData(_, loc)
+            | Self::DataAlreadyExists(_, loc)
+            | Self::DuplicateConstructor(_, loc)
+            | Self::RuleArity { loc, .. }
+            | Self::RulesDontCompose { loc, .. }
+            | Self::TargetNotReached { loc, .. } => loc,
         }
     }
+
+    /// Whether the error only means that the `apply_rule` call can't be
+    /// carried out on the expression it was matched with
+    fn is_mismatch(&self) -> bool {
+        matches!(
+            self,
+            Self::NoMatch(_)
+                | Self::NotARule(..)
+                | Self::RuleDoesNotExist(..)
+                | Self::AmbiguousRule(..)
+                | Self::UnknownStrategy(..)
+                | Self::StrategyIsNotSym(..)
+                | Self::IrreversibleRule(_)
+        )
+    }
 }
 
 #[derive(Debug)]
@@ -226,7 +856,12 @@ enum AppliedRule {
     ByName {
         loc: Loc,
         name: String,
+        /// Arguments of the parameterized rule, like `2` in `mul_by(2)`
+        args: Vec<Expr>,
         reversed: bool,
+        /// Values of the variables of the rule given at the application
+        /// site, like `sum_id[A := x]`
+        bindings: Vec<(String, Expr)>,
     },
     Anonymous {
         loc: Loc,
@@ -256,10 +891,81 @@ struct Resolution {
 
 #[derive(Debug, Clone)]
 enum Rule {
-    User { loc: Loc, head: Expr, body: Expr },
+    User {
+        loc: Loc,
+        head: Expr,
+        body: Expr,
+        /// Variables that get their values from the arguments of the
+        /// rule at the application site, like `K` in `mul_by(K)`
+        params: Vec<String>,
+    },
+    /// Several [`Rule::User`] clauses under one name. The first clause
+    /// that matches is applied.
+    Clauses(Vec<Rule>),
     Replace,
 }
 
+/// How a derived rule is built from the other rules
+#[derive(Debug, Clone)]
+enum Derivation {
+    /// An existing rule with the arguments if it's parameterized
+    Rule { name: String, args: Vec<Expr> },
+    /// The reversed rule `sym(r)`
+    Sym(Box<Derivation>),
+    /// One rule applied after another `r1 ; r2`
+    Seq(Box<Derivation>, Box<Derivation>),
+}
+
+impl Derivation {
+    /// Single step of the derivation written as `r`, `r(args)` or `sym(r)`
+    fn from_expr(expr: &Expr) -> Option<Self> {
+        match expr {
+            Expr::Sym(name) => Some(Self::Rule {
+                name: name.clone(),
+                args: vec![],
+            }),
+            Expr::Fun(head, args) if **head == expr!(sym) && args.len() == 1 => {
+                Some(Self::Sym(Box::new(Self::from_expr(&args[0])?)))
+            }
+            Expr::Fun(head, args) => match &**head {
+                Expr::Sym(name) => Some(Self::Rule {
+                    name: name.clone(),
+                    args: args.clone(),
+                }),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Names of the rules the derivation is built from
+    fn rule_names(&self) -> Vec<&str> {
+        match self {
+            Self::Rule { name, .. } => vec![name],
+            Self::Sym(derivation) => derivation.rule_names(),
+            Self::Seq(first, second) => {
+                let mut names = first.rule_names();
+                names.extend(second.rule_names());
+                names
+            }
+        }
+    }
+}
+
+impl fmt::Display for Derivation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Rule { name, args } => {
+                let mut sink = Vec::new();
+                write_applied_rule_name(&mut sink, name, args).map_err(|_| fmt::Error)?;
+                write!(f, "{}", String::from_utf8_lossy(&sink))
+            }
+            Self::Sym(derivation) => write!(f, "sym({})", derivation),
+            Self::Seq(first, second) => write!(f, "{} ; {}", first, second),
+        }
+    }
+}
+
 enum Strategy {
     All,
     Deep,
@@ -276,6 +982,43 @@ impl Strategy {
         }
     }
 
+    /// Strategy argument of the meta rule `apply_rule`. Either the name
+    /// of the strategy or the name applied to the traversal modifiers,
+    /// like `all(bottomup, rightleft)`.
+    fn from_expr(expr: &Expr, loc: &Loc) -> Result<(Self, Traversal), RuntimeError> {
+        let (name, modifiers) = match expr {
+            Expr::Sym(name) => (name, &[][..]),
+            Expr::Fun(head, args) => match &**head {
+                Expr::Sym(name) => (name, &args[..]),
+                _ => return Err(RuntimeError::StrategyIsNotSym(expr.clone(), loc.clone())),
+            },
+            _ => return Err(RuntimeError::StrategyIsNotSym(expr.clone(), loc.clone())),
+        };
+        let strategy = Self::by_name(name)
+            .ok_or_else(|| RuntimeError::UnknownStrategy(name.clone(), loc.clone()))?;
+        let mut traversal = Traversal::default();
+        for modifier in modifiers {
+            match modifier {
+                Expr::Sym(name) => {
+                    if let Some(order) = Order::by_name(name) {
+                        traversal.order = order
+                    } else if let Some(right_to_left) = Traversal::direction_by_name(name) {
+                        traversal.right_to_left = right_to_left
+                    } else {
+                        return Err(RuntimeError::UnknownStrategy(name.clone(), loc.clone()));
+                    }
+                }
+                _ => {
+                    return Err(RuntimeError::StrategyIsNotSym(
+                        modifier.clone(),
+                        loc.clone(),
+                    ))
+                }
+            }
+        }
+        Ok((strategy, traversal))
+    }
+
     fn matched(&self, index: usize) -> Resolution {
         match self {
             Self::All => Resolution {
@@ -311,142 +1054,599 @@ impl Strategy {
     }
 }
 
-impl Rule {
-    fn apply(
-        &self,
-        expr: &Expr,
-        strategy: &Strategy,
-        apply_command_loc: &Loc,
-    ) -> Result<Expr, RuntimeError> {
-        fn apply_to_subexprs(
-            rule: &Rule,
-            expr: &Expr,
-            strategy: &Strategy,
-            apply_command_loc: &Loc,
-            match_count: &mut usize,
-        ) -> Result<(Expr, bool), RuntimeError> {
-            use Expr::*;
-            match expr {
-                Sym(_) | Var(_) => Ok((expr.clone(), false)),
-                Op(op, lhs, rhs) => {
-                    let (new_lhs, halt) =
-                        apply_impl(rule, lhs, strategy, apply_command_loc, match_count)?;
-                    if halt {
-                        return Ok((Op(*op, Box::new(new_lhs), rhs.clone()), true));
-                    }
-                    let (new_rhs, halt) =
-                        apply_impl(rule, rhs, strategy, apply_command_loc, match_count)?;
-                    Ok((Op(*op, Box::new(new_lhs), Box::new(new_rhs)), halt))
-                }
-                Fun(head, args) => {
-                    let (new_head, halt) =
-                        apply_impl(rule, head, strategy, apply_command_loc, match_count)?;
-                    if halt {
-                        Ok((Fun(Box::new(new_head), args.clone()), true))
-                    } else {
-                        let mut new_args = Vec::<Expr>::new();
-                        let mut halt_args = false;
-                        for arg in args {
-                            if halt_args {
-                                new_args.push(arg.clone())
-                            } else {
-                                let (new_arg, halt) = apply_impl(
-                                    rule,
-                                    arg,
-                                    strategy,
-                                    apply_command_loc,
-                                    match_count,
-                                )?;
-                                new_args.push(new_arg);
-                                halt_args = halt;
-                            }
-                        }
-                        Ok((Fun(Box::new(new_head), new_args), false))
-                    }
-                }
-            }
-        }
+/// The order in which the subexpressions are visited while looking
+/// for the matches of a rule
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+enum Order {
+    /// The expression itself is tried before its subexpressions
+    #[default]
+    TopDown,
+    /// The subexpressions are tried before the expression itself
+    /// (innermost first)
+    BottomUp,
+    /// The expression is tried level by level
+    Breadth,
+}
 
-        fn apply_impl(
-            rule: &Rule,
-            expr: &Expr,
-            strategy: &Strategy,
-            apply_command_loc: &Loc,
-            match_count: &mut usize,
-        ) -> Result<(Expr, bool), RuntimeError> {
-            match rule {
-                Rule::User { loc: _, head, body } => {
-                    if let Some(bindings) = head.pattern_match(expr) {
-                        let resolution = strategy.matched(*match_count);
-                        *match_count += 1;
-                        let new_expr = match resolution.action {
-                            Action::Apply => body.substitute(&bindings),
-                            Action::Skip => expr.clone(),
-                        };
-                        match resolution.state {
-                            State::Bail => Ok((new_expr, false)),
-                            State::Cont => apply_to_subexprs(
-                                rule,
-                                &new_expr,
-                                strategy,
-                                apply_command_loc,
-                                match_count,
-                            ),
-                            State::Halt => Ok((new_expr, true)),
-                        }
-                    } else {
-                        apply_to_subexprs(rule, expr, strategy, apply_command_loc, match_count)
-                    }
-                }
+impl Order {
+    fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "topdown" => Some(Self::TopDown),
+            "bottomup" => Some(Self::BottomUp),
+            "breadth" => Some(Self::Breadth),
+            _ => None,
+        }
+    }
 
-                Rule::Replace => {
-                    if let Some(bindings) =
-                        expr!(apply_rule(Strategy, Head, Body, Expr)).pattern_match(expr)
-                    {
-                        *match_count += 1;
-                        let meta_rule = Rule::User {
-                            loc: loc_here!(),
-                            head: bindings
-                                .get("Head")
-                                .expect("Variable `Head` is present in the meta pattern")
-                                .clone(),
-                            body: bindings
-                                .get("Body")
-                                .expect("Variable `Body` is present in the meta pattern")
-                                .clone(),
-                        };
-                        let meta_strategy = bindings
-                            .get("Strategy")
-                            .expect("Variable `Strategy` is present in the meta pattern");
-                        if let Expr::Sym(meta_strategy_name) = meta_strategy {
-                            let meta_expr = bindings
-                                .get("Expr")
-                                .expect("Variable `Expr` is present in the meta pattern");
-                            let result = match Strategy::by_name(meta_strategy_name) {
-                                Some(strategy) => {
-                                    meta_rule.apply(meta_expr, &strategy, apply_command_loc)
-                                }
-                                None => Err(RuntimeError::UnknownStrategy(
-                                    meta_strategy_name.to_string(),
-                                    apply_command_loc.clone(),
-                                )),
-                            };
-                            Ok((result?, false))
-                        } else {
-                            Err(RuntimeError::StrategyIsNotSym(
-                                meta_strategy.clone(),
-                                apply_command_loc.clone(),
-                            ))
-                        }
-                    } else {
-                        apply_to_subexprs(rule, expr, strategy, apply_command_loc, match_count)
+    fn name(&self) -> &'static str {
+        match self {
+            Self::TopDown => "topdown",
+            Self::BottomUp => "bottomup",
+            Self::Breadth => "breadth",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Traversal {
+    order: Order,
+    right_to_left: bool,
+}
+
+impl Traversal {
+    fn direction_by_name(name: &str) -> Option<bool> {
+        match name {
+            "leftright" => Some(false),
+            "rightleft" => Some(true),
+            _ => None,
+        }
+    }
+
+    /// Names of the modifiers that follow the strategy name to get this
+    /// traversal. The default traversal has no modifiers.
+    fn modifier_names(&self) -> Vec<&'static str> {
+        let mut names = Vec::new();
+        if self.order != Order::default() {
+            names.push(self.order.name())
+        }
+        if self.right_to_left {
+            names.push("rightleft")
+        }
+        names
+    }
+
+    /// Indices of `count` subexpressions in the order they are visited
+    fn indices(&self, count: usize) -> Box<dyn Iterator<Item = usize>> {
+        if self.right_to_left {
+            Box::new((0..count).rev())
+        } else {
+            Box::new(0..count)
+        }
+    }
+}
+
+/// Side of the equation a rule is applied to
+#[derive(Debug, Clone, Copy)]
+enum Side {
+    Left,
+    Right,
+    Both,
+}
+
+impl Side {
+    fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "left" => Some(Self::Left),
+            "right" => Some(Self::Right),
+            "both" => Some(Self::Both),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Left => "left",
+            Self::Right => "right",
+            Self::Both => "both",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Limit {
+    /// Maximum amount of nodes in an expression
+    Nodes,
+    /// Maximum depth of an expression
+    Depth,
+    /// Maximum amount of rewrites done by a single command
+    Steps,
+}
+
+impl Limit {
+    fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "nodes" => Some(Self::Nodes),
+            "depth" => Some(Self::Depth),
+            "steps" => Some(Self::Steps),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Nodes => "nodes",
+            Self::Depth => "depth",
+            Self::Steps => "steps",
+        }
+    }
+}
+
+/// What happens to the variables of the rule body that don't appear in
+/// its head, so the match gives them no values
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Unbound {
+    /// Such rules are refused
+    Error,
+    /// Such rules are allowed with a warning and the variables are left
+    /// in the expression as they are
+    Warn,
+    /// The variables are replaced with fresh symbols when the rule is
+    /// applied
+    Fresh,
+}
+
+impl Unbound {
+    fn by_name(name: &str) -> Option<Self> {
+        match name {
+            "error" => Some(Self::Error),
+            "warn" => Some(Self::Warn),
+            "fresh" => Some(Self::Fresh),
+            _ => None,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Self::Error => "error",
+            Self::Warn => "warn",
+            Self::Fresh => "fresh",
+        }
+    }
+}
+
+/// Limits that keep careless rewriting from eating all the memory
+#[derive(Debug, Clone, Copy)]
+struct Limits {
+    max_nodes: usize,
+    max_depth: usize,
+    max_steps: usize,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_nodes: 100_000,
+            max_depth: 1_000,
+            max_steps: 10_000,
+        }
+    }
+}
+
+impl Limits {
+    fn set(&mut self, limit: Limit, value: usize) {
+        match limit {
+            Limit::Nodes => self.max_nodes = value,
+            Limit::Depth => self.max_depth = value,
+            Limit::Steps => self.max_steps = value,
+        }
+    }
+
+    fn check_steps(&self, steps: usize, loc: &Loc) -> Result<(), RuntimeError> {
+        if steps > self.max_steps {
+            return Err(RuntimeError::LimitExceeded(
+                Limit::Steps,
+                self.max_steps,
+                loc.clone(),
+            ));
+        }
+        Ok(())
+    }
+
+    fn check_expr(&self, expr: &Expr, loc: &Loc) -> Result<(), RuntimeError> {
+        if expr.size() > self.max_nodes {
+            return Err(RuntimeError::LimitExceeded(
+                Limit::Nodes,
+                self.max_nodes,
+                loc.clone(),
+            ));
+        }
+        if expr.depth() > self.max_depth {
+            return Err(RuntimeError::LimitExceeded(
+                Limit::Depth,
+                self.max_depth,
+                loc.clone(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+impl Rule {
+    /// Substitutes the values of the variables given at the application
+    /// site into the head and the body of the rule. The other variables
+    /// of the rule are renamed, so the variables of the values don't get
+    /// mixed up with them.
+    fn bind(self, bindings: &[(String, Expr)], loc: &Loc) -> Result<Rule, RuntimeError> {
+        if bindings.is_empty() {
+            return Ok(self);
+        }
+        let vars = self.vars();
+        for (name, _) in bindings {
+            if !vars.contains(name) {
+                return Err(RuntimeError::UnknownVariable(name.clone(), loc.clone()));
+            }
+        }
+        let mut bindings: HashMap<String, Expr> = bindings.iter().cloned().collect();
+        let value_vars: BTreeSet<String> =
+            bindings.values().flat_map(|value| value.vars()).collect();
+        let mut taken: BTreeSet<String> = vars.union(&value_vars).cloned().collect();
+        for name in &vars {
+            if bindings.contains_key(name) || !value_vars.contains(name) || name == "_" {
+                continue;
+            }
+            let fresh = (1..)
+                .map(|index| format!("{}_{}", name, index))
+                .find(|fresh| !taken.contains(fresh))
+                .expect("There are infinitely many candidates");
+            taken.insert(fresh.clone());
+            bindings.insert(name.clone(), Expr::Var(fresh));
+        }
+        Ok(self.bind_clauses(&bindings))
+    }
+
+    fn bind_clauses(self, bindings: &HashMap<String, Expr>) -> Rule {
+        match self {
+            Rule::User {
+                loc: rule_loc,
+                head,
+                body,
+                params,
+            } => Rule::User {
+                loc: rule_loc,
+                head: head.substitute(bindings),
+                body: body.substitute(bindings),
+                params: params
+                    .into_iter()
+                    .filter(|param| !bindings.contains_key(param))
+                    .collect(),
+            },
+            Rule::Clauses(clauses) => Rule::Clauses(
+                clauses
+                    .into_iter()
+                    .map(|clause| clause.bind_clauses(bindings))
+                    .collect(),
+            ),
+            Rule::Replace => Rule::Replace,
+        }
+    }
+
+    /// All the variables of the rule
+    fn vars(&self) -> BTreeSet<String> {
+        match self {
+            Rule::User { head, body, .. } => {
+                let mut vars = head.vars();
+                vars.extend(body.vars());
+                vars
+            }
+            Rule::Clauses(clauses) => clauses.iter().flat_map(|clause| clause.vars()).collect(),
+            Rule::Replace => BTreeSet::new(),
+        }
+    }
+
+    /// The clauses of the rule, a single one unless it's [`Rule::Clauses`]
+    fn clauses(&self) -> &[Rule] {
+        match self {
+            Rule::Clauses(clauses) => clauses,
+            _ => std::slice::from_ref(self),
+        }
+    }
+
+    /// Builds the rule out of the clauses
+    fn from_clauses(mut clauses: Vec<Rule>) -> Rule {
+        if clauses.len() == 1 {
+            clauses.pop().expect("The length was checked above")
+        } else {
+            Rule::Clauses(clauses)
+        }
+    }
+
+    fn loc(&self) -> Option<&Loc> {
+        match self {
+            Rule::User { loc, .. } => Some(loc),
+            Rule::Clauses(clauses) => clauses.first().and_then(|clause| clause.loc()),
+            Rule::Replace => None,
+        }
+    }
+
+    /// The same rule defined at `loc`
+    fn defined_at(self, loc: &Loc) -> Rule {
+        match self {
+            Rule::User {
+                head, body, params, ..
+            } => Rule::User {
+                loc: loc.clone(),
+                head,
+                body,
+                params,
+            },
+            Rule::Clauses(clauses) => Rule::Clauses(
+                clauses
+                    .into_iter()
+                    .map(|clause| clause.defined_at(loc))
+                    .collect(),
+            ),
+            Rule::Replace => Rule::Replace,
+        }
+    }
+
+    fn params(&self) -> &[String] {
+        match self {
+            Rule::User { params, .. } => params,
+            Rule::Clauses(clauses) => clauses.first().map_or(&[], |clause| clause.params()),
+            Rule::Replace => &[],
+        }
+    }
+
+    /// Variables of the body that don't get their values from the match
+    /// of the head or from the arguments of the rule
+    fn unbound_vars(&self) -> Vec<String> {
+        match self {
+            Rule::User {
+                head, body, params, ..
+            } => {
+                let head_vars = head.vars();
+                body.vars()
+                    .into_iter()
+                    .filter(|name| !head_vars.contains(name) && !params.contains(name))
+                    .collect()
+            }
+            Rule::Clauses(clauses) => {
+                let unbound: BTreeSet<String> = clauses
+                    .iter()
+                    .flat_map(|clause| clause.unbound_vars())
+                    .collect();
+                unbound.into_iter().collect()
+            }
+            Rule::Replace => vec![],
+        }
+    }
+
+    fn reversed(&self, loc: &Loc) -> Result<Rule, RuntimeError> {
+        match self.clone() {
+            Rule::User {
+                loc,
+                head,
+                body,
+                params,
+            } => Ok(Rule::User {
+                loc,
+                head: body,
+                body: head,
+                params,
+            }),
+            Rule::Clauses(clauses) => Ok(Rule::Clauses(
+                clauses
+                    .iter()
+                    .map(|clause| clause.reversed(loc))
+                    .collect::<Result<Vec<Rule>, RuntimeError>>()?,
+            )),
+            Rule::Replace => Err(RuntimeError::IrreversibleRule(loc.clone())),
+        }
+    }
+
+    fn pattern_match(&self, expr: &Expr) -> Option<HashMap<String, Expr>> {
+        self.matching_clause(expr).map(|(_, bindings)| bindings)
+    }
+
+    /// The first clause of the rule that matches the expression along
+    /// with the bindings of the match
+    fn matching_clause(&self, expr: &Expr) -> Option<(&Rule, HashMap<String, Expr>)> {
+        match self {
+            Rule::User { head, .. } => head.pattern_match(expr).map(|bindings| (self, bindings)),
+            Rule::Clauses(clauses) => clauses
+                .iter()
+                .find_map(|clause| clause.matching_clause(expr)),
+            Rule::Replace => expr!(apply_rule(Strategy, Head, Body, Expr))
+                .pattern_match(expr)
+                .or_else(|| expr!(apply_rule(Strategy, Rule, Expr)).pattern_match(expr))
+                .map(|bindings| (self, bindings)),
+        }
+    }
+
+    fn substitute(
+        &self,
+        bindings: &HashMap<String, Expr>,
+        context: &Context,
+        apply_command_loc: &Loc,
+    ) -> Result<Expr, RuntimeError> {
+        match self {
+            Rule::User { body, .. } => Ok(body.substitute(bindings)),
+            Rule::Clauses(_) => {
+                unreachable!("The clause is picked by the match, see `Rule::matching_clause`")
+            }
+            Rule::Replace => {
+                let mut bindings = bindings.clone();
+                for value in bindings.values_mut() {
+                    *value = Self::eval_meta(value, context, apply_command_loc)?;
+                }
+                Self::apply_meta(&bindings, context, apply_command_loc)
+            }
+        }
+    }
+
+    /// Evaluates all the `apply_rule` calls in the expression innermost
+    /// first, so the arguments of a call are evaluated before the call
+    fn eval_meta(expr: &Expr, context: &Context, loc: &Loc) -> Result<Expr, RuntimeError> {
+        let mut subexprs = Vec::new();
+        for subexpr in expr.subexprs() {
+            subexprs.push(Self::eval_meta(subexpr, context, loc)?);
+        }
+        let expr = expr.with_subexprs(subexprs);
+        match Rule::Replace.pattern_match(&expr) {
+            Some(bindings) => Self::apply_meta(&bindings, context, loc),
+            None => Ok(expr),
+        }
+    }
+
+    /// Performs the `apply_rule` call with already evaluated arguments
+    fn apply_meta(
+        bindings: &HashMap<String, Expr>,
+        context: &Context,
+        loc: &Loc,
+    ) -> Result<Expr, RuntimeError> {
+        let meta_rule = match bindings.get("Rule") {
+            Some(rule) => {
+                let rule = context.rule_from_expr(rule, loc)?;
+                let meta_expr = bindings
+                    .get("Expr")
+                    .expect("Variable `Expr` is present in the meta pattern");
+                context.handle_unbound(rule, meta_expr, loc)?
+            }
+            None => Rule::User {
+                loc: loc_here!(),
+                head: bindings
+                    .get("Head")
+                    .expect("Variable `Head` is present in the meta pattern")
+                    .clone(),
+                body: bindings
+                    .get("Body")
+                    .expect("Variable `Body` is present in the meta pattern")
+                    .clone(),
+                params: vec![],
+            },
+        };
+        let (meta_strategy, meta_traversal) = Strategy::from_expr(
+            bindings
+                .get("Strategy")
+                .expect("Variable `Strategy` is present in the meta pattern"),
+            loc,
+        )?;
+        let meta_expr = bindings
+            .get("Expr")
+            .expect("Variable `Expr` is present in the meta pattern");
+        meta_rule.apply(meta_expr, &meta_strategy, &meta_traversal, context, loc)
+    }
+
+    /// All the results of applying the rule at exactly one position
+    /// along with the number the `Nth` strategy gives to the match with
+    /// the default traversal. The positions where the `apply_rule` call
+    /// can't be carried out give no results, but they are still counted.
+    fn rewrites(
+        &self,
+        expr: &Expr,
+        context: &Context,
+        apply_command_loc: &Loc,
+    ) -> Result<Vec<(usize, Expr)>, RuntimeError> {
+        fn rewrites_impl(
+            rule: &Rule,
+            expr: &Expr,
+            context: &Context,
+            apply_command_loc: &Loc,
+            matches: &mut usize,
+        ) -> Result<Vec<(usize, Expr)>, RuntimeError> {
+            let mut rewrites = Vec::new();
+            if let Some((clause, bindings)) = rule.matching_clause(expr) {
+                let index = *matches;
+                *matches += 1;
+                match clause.substitute(&bindings, context, apply_command_loc) {
+                    Ok(new_expr) => rewrites.push((index, new_expr)),
+                    Err(err) if err.is_mismatch() => {}
+                    Err(err) => return Err(err),
+                }
+            }
+            let subexprs = expr.subexprs();
+            for (position, subexpr) in subexprs.iter().enumerate() {
+                for (index, new_subexpr) in
+                    rewrites_impl(rule, subexpr, context, apply_command_loc, matches)?
+                {
+                    let mut new_subexprs: Vec<Expr> = subexprs.iter().map(|&x| x.clone()).collect();
+                    new_subexprs[position] = new_subexpr;
+                    rewrites.push((index, expr.with_subexprs(new_subexprs)));
+                }
+            }
+            Ok(rewrites)
+        }
+
+        rewrites_impl(self, expr, context, apply_command_loc, &mut 0)
+    }
+
+    /// The subexpressions the rule matches in the order the `Nth`
+    /// strategy numbers them with the given traversal
+    fn occurrences<'a>(&self, expr: &'a Expr, traversal: &Traversal) -> Vec<&'a Expr> {
+        let mut occurrences = Vec::new();
+        match traversal.order {
+            Order::TopDown | Order::BottomUp => {
+                fn visit<'a>(
+                    rule: &Rule,
+                    expr: &'a Expr,
+                    traversal: &Traversal,
+                    occurrences: &mut Vec<&'a Expr>,
+                ) {
+                    let here = rule.pattern_match(expr).is_some();
+                    if here && traversal.order == Order::TopDown {
+                        occurrences.push(expr);
+                    }
+                    let subexprs = expr.subexprs();
+                    for index in traversal.indices(subexprs.len()) {
+                        visit(rule, subexprs[index], traversal, occurrences);
+                    }
+                    if here && traversal.order == Order::BottomUp {
+                        occurrences.push(expr);
+                    }
+                }
+                visit(self, expr, traversal, &mut occurrences);
+            }
+            Order::Breadth => {
+                let mut queue = VecDeque::from([expr]);
+                while let Some(subexpr) = queue.pop_front() {
+                    if self.pattern_match(subexpr).is_some() {
+                        occurrences.push(subexpr);
+                    }
+                    let subexprs = subexpr.subexprs();
+                    for index in traversal.indices(subexprs.len()) {
+                        queue.push_back(subexprs[index]);
                     }
                 }
             }
         }
-        let mut match_count = 0;
-        let result = (apply_impl(self, expr, strategy, apply_command_loc, &mut match_count)?).0;
-        if match_count > 0 {
+        occurrences
+    }
+
+    fn apply(
+        &self,
+        expr: &Expr,
+        strategy: &Strategy,
+        traversal: &Traversal,
+        context: &Context,
+        apply_command_loc: &Loc,
+    ) -> Result<Expr, RuntimeError> {
+        let limits = &context.limits;
+        let mut application = Application {
+            rule: self,
+            strategy,
+            traversal,
+            context,
+            apply_command_loc,
+            match_count: 0,
+            steps: 0,
+            level: 0,
+        };
+        let result = match traversal.order {
+            Order::Breadth => application.apply_breadth_first(expr)?,
+            Order::TopDown | Order::BottomUp => application.apply_impl(expr)?.0,
+        };
+        if application.match_count > 0 {
+            limits.check_expr(&result, apply_command_loc)?;
             Ok(result)
         } else {
             Err(RuntimeError::NoMatch(apply_command_loc.clone()))
@@ -454,6 +1654,136 @@ impl Rule {
     }
 }
 
+/// State of a single application of a rule to an expression
+struct Application<'a> {
+    rule: &'a Rule,
+    strategy: &'a Strategy,
+    traversal: &'a Traversal,
+    context: &'a Context,
+    apply_command_loc: &'a Loc,
+    match_count: usize,
+    /// Amount of rewrites done so far
+    steps: usize,
+    /// Depth of the currently visited subexpression
+    level: usize,
+}
+
+impl Application<'_> {
+    /// Tries the rule on the expression itself without looking into
+    /// its subexpressions
+    fn apply_here(&mut self, expr: &Expr) -> Result<Option<(Expr, Resolution)>, RuntimeError> {
+        if let Some((clause, bindings)) = self.rule.matching_clause(expr) {
+            let resolution = self.strategy.matched(self.match_count);
+            self.match_count += 1;
+            let new_expr = match resolution.action {
+                Action::Apply => {
+                    let limits = &self.context.limits;
+                    self.steps += 1;
+                    limits.check_steps(self.steps, self.apply_command_loc)?;
+                    let new_expr =
+                        clause.substitute(&bindings, self.context, self.apply_command_loc)?;
+                    limits.check_expr(&new_expr, self.apply_command_loc)?;
+                    if self.level + new_expr.depth() > limits.max_depth {
+                        return Err(RuntimeError::LimitExceeded(
+                            Limit::Depth,
+                            limits.max_depth,
+                            self.apply_command_loc.clone(),
+                        ));
+                    }
+                    new_expr
+                }
+                Action::Skip => expr.clone(),
+            };
+            Ok(Some((new_expr, resolution)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn apply_to_subexprs(&mut self, expr: &Expr) -> Result<(Expr, bool), RuntimeError> {
+        let subexprs = expr.subexprs();
+        let mut new_subexprs: Vec<Expr> = subexprs.iter().map(|&x| x.clone()).collect();
+        let mut halt = false;
+        self.level += 1;
+        for index in self.traversal.indices(subexprs.len()) {
+            let (new_subexpr, halt_subexpr) = self.apply_impl(subexprs[index])?;
+            new_subexprs[index] = new_subexpr;
+            if halt_subexpr {
+                halt = true;
+                break;
+            }
+        }
+        self.level -= 1;
+        Ok((expr.with_subexprs(new_subexprs), halt))
+    }
+
+    fn apply_impl(&mut self, expr: &Expr) -> Result<(Expr, bool), RuntimeError> {
+        match self.traversal.order {
+            Order::TopDown | Order::Breadth => match self.apply_here(expr)? {
+                Some((new_expr, resolution)) => match resolution.state {
+                    State::Bail => Ok((new_expr, false)),
+                    State::Cont => self.apply_to_subexprs(&new_expr),
+                    State::Halt => Ok((new_expr, true)),
+                },
+                None => self.apply_to_subexprs(expr),
+            },
+            Order::BottomUp => {
+                let (expr, halt) = self.apply_to_subexprs(expr)?;
+                if halt {
+                    return Ok((expr, true));
+                }
+                match self.apply_here(&expr)? {
+                    // The subexpressions were already visited, so
+                    // skipping a match just moves on upwards
+                    Some((
+                        new_expr,
+                        Resolution {
+                            action: Action::Skip,
+                            state,
+                        },
+                    )) => Ok((new_expr, matches!(state, State::Halt))),
+                    Some((new_expr, resolution)) => match resolution.state {
+                        State::Bail => Ok((new_expr, false)),
+                        State::Cont => self.apply_to_subexprs(&new_expr),
+                        State::Halt => Ok((new_expr, true)),
+                    },
+                    None => Ok((expr, false)),
+                }
+            }
+        }
+    }
+
+    fn apply_breadth_first(&mut self, expr: &Expr) -> Result<Expr, RuntimeError> {
+        let mut result = expr.clone();
+        let mut queue = VecDeque::from([Vec::<usize>::new()]);
+        while let Some(path) = queue.pop_front() {
+            let subexpr = result
+                .subexpr_at_mut(&path)
+                .expect("Paths in the queue always point at existing subexpressions");
+            self.level = path.len();
+            let descend = match self.apply_here(subexpr)? {
+                Some((new_expr, resolution)) => {
+                    *subexpr = new_expr;
+                    match resolution.state {
+                        State::Bail => false,
+                        State::Cont => true,
+                        State::Halt => break,
+                    }
+                }
+                None => true,
+            };
+            if descend {
+                for index in self.traversal.indices(subexpr.subexprs().len()) {
+                    let mut subpath = path.clone();
+                    subpath.push(index);
+                    queue.push_back(subpath);
+                }
+            }
+        }
+        Ok(result)
+    }
+}
+
 #[derive(Clone)]
 enum Command {
     /// Define rule
@@ -461,8 +1791,17 @@ enum Command {
     /// Example:
     /// ```noq
     /// sum_comm :: A + B = B + A
+    /// @simp sum_id :: 0 + A = A # <- tagged rule definition
+    /// redefine sum_id :: A + 0 = A # <- replaces the existing rule
     /// ```
-    DefineRule(Loc, String, Rule),
+    DefineRule {
+        loc: Loc,
+        name: String,
+        rule: Rule,
+        tags: Vec<String>,
+        /// The rule is allowed to replace the existing one
+        redefine: bool,
+    },
     /// Define rule via shaping
     ///
     /// Starts the process of shaping and defines a rule after it's done
@@ -472,8 +1811,54 @@ enum Command {
     /// sum_comm :: A + B { # <- the define rule via shaping command
     ///   ...
     /// }
+    ///
+    /// lemma sum_comm :: A + B == B + A { # <- the shaping must reach B + A
+    ///   ...
+    /// }
+    ///
+    /// sum_comm :: forall a, b. a + b { # <- a and b become variables at the end
+    ///   ...
+    /// }
+    /// ```
+    DefineRuleViaShaping {
+        name: String,
+        expr: Expr,
+        /// The expression the shaping must end with
+        target: Option<Expr>,
+        /// Symbols that become variables of the rule when the shaping is
+        /// finished
+        forall: Vec<String>,
+        tags: Vec<String>,
+        /// The rule is allowed to replace the existing one
+        redefine: bool,
+    },
+    /// Assume a rule for the rest of the current shaping
+    ///
+    /// The rule is removed when the shaping is finished.
+    ///
+    /// Example:
+    /// ```noq
+    /// x*(x + y) {
+    ///   assume h :: x + y = 3 # <- the assume command
+    ///   h | all
+    /// }
     /// ```
-    DefineRuleViaShaping { name: String, expr: Expr },
+    Assume { loc: Loc, name: String, rule: Rule },
+    /// Define rule derived from the existing rules
+    ///
+    /// Example:
+    /// ```noq
+    /// sum_id_comm :: sum_comm ; sum_id # <- applies sum_comm, then sum_id
+    /// sum_id_rev  :: sym(sum_id)       # <- the reversed sum_id
+    /// ```
+    DefineDerivedRule {
+        loc: Loc,
+        name: String,
+        derivation: Derivation,
+        tags: Vec<String>,
+        /// The rule is allowed to replace the existing one
+        redefine: bool,
+    },
     /// Starting shaping
     ///
     /// Example:
@@ -489,36 +1874,213 @@ enum Command {
     /// ```noq
     /// name :: ... {
     ///   ...
-    ///   sum_comm      | all # <- the apply rule command
-    ///   A + B = B + A | all # <- another apply rule command
+    ///   sum_comm      | all          # <- the apply rule command
+    ///   A + B = B + A | all          # <- another apply rule command
+    ///   sum_id        | all bottomup # <- innermost matches first
     ///   ...
     /// }
+    ///
+    /// a + 0 == 0 + a {
+    ///   left: sum_id | all # <- only rewrites the left side of the equation
+    /// }
     /// ```
     ApplyRule {
         loc: Loc,
         strategy_name: String,
+        traversal: Traversal,
         applied_rule: AppliedRule,
+        /// Side of the shaped equation the rule is applied to. The rule
+        /// is applied to the whole expression if it's absent.
+        side: Option<Side>,
     },
-    /// Finish the process of shaping
+    /// Declare binary operators associative and commutative. Equations
+    /// are closed when their sides are equal modulo the declared theory.
     ///
     /// Example:
     /// ```noq
-    /// A + B :: {
-    ///   ...
-    /// } # <- the finish shaping command
+    /// theory ac +, * # <- the theory command
     /// ```
-    FinishShaping(Loc),
-    /// Undo previusly applied rule
+    Theory(Loc, Vec<Op>),
+    /// Show where the rule matches the shaped expression, numbered the
+    /// way the `Nth` strategy numbers the matches
     ///
     /// Example:
     /// ```noq
-    /// A + B :: {
+    /// name :: ... {
     ///   ...
-    ///   undo # <- the undo command
+    ///   sum_comm ?          # <- the matches command
+    ///   matches sum_comm    # <- the same
+    ///   sum_comm ? bottomup # <- numbered for the bottomup traversal
+    ///   A + B = B + A ?     # <- the anonymous rule
     ///   ...
     /// }
     /// ```
-    UndoRule(Loc),
+    Matches {
+        loc: Loc,
+        traversal: Traversal,
+        applied_rule: AppliedRule,
+    },
+    /// Shape a subexpression of the shaped expression. The result is
+    /// put back in place of the subexpression when the shaping is
+    /// finished.
+    ///
+    /// The path goes through the subexpressions level by level. The
+    /// subexpressions of a functor are its head followed by the
+    /// arguments, so `f(a, b)` has `a` at `1` and `b` at `2`.
+    ///
+    /// Example:
+    /// ```noq
+    /// (a + b)*(c + d) {
+    ///   focus 1 { # <- the focus command, shapes c + d
+    ///     sum_comm | all
+    ///   }
+    /// }
+    /// ```
+    Focus(Loc, Vec<usize>),
+    /// Split the shaped equation into a goal per case of the expression.
+    /// The cases must cover every value of the data type they are made
+    /// of. The goals are shaped one after another, the next one becomes
+    /// active when the previous one is closed.
+    ///
+    /// Example:
+    /// ```noq
+    /// sum_zero :: N + 0 == N {
+    ///   cases N of 0 | s(M) # <- the cases command
+    ///   ...
+    /// }
+    /// ```
+    Cases {
+        loc: Loc,
+        subject: Expr,
+        patterns: Vec<Expr>,
+    },
+    /// Split the shaped equation into a goal per constructor of the data
+    /// type. The goals of the recursive constructors get the induction
+    /// hypotheses.
+    ///
+    /// Example:
+    /// ```noq
+    /// sum_zero :: N + 0 == N {
+    ///   induction N on nat # <- the induction command
+    ///   ...
+    /// }
+    /// ```
+    Induction {
+        loc: Loc,
+        subject: Expr,
+        data: String,
+    },
+    /// Show the most specific pattern that matches both expressions, so
+    /// it can be used as the head of a rule
+    ///
+    /// Example:
+    /// ```noq
+    /// generalize 2*(x + y), 3*(x + z) # <- the generalize command, gives A*(x + B)
+    /// ```
+    Generalize(Loc, Expr, Expr),
+    /// Declare a data type by its constructors. The arguments of the
+    /// constructors are the types of the arguments.
+    ///
+    /// Example:
+    /// ```noq
+    /// data nat = 0 | s(nat) # <- the data command
+    /// ```
+    Data {
+        loc: Loc,
+        name: String,
+        constructors: Vec<Expr>,
+    },
+    /// Finish the process of shaping
+    ///
+    /// The shaping can be required to end with the given expression.
+    ///
+    /// Example:
+    /// ```noq
+    /// A + B :: {
+    ///   ...
+    /// } # <- the finish shaping command
+    ///
+    /// A + B {
+    ///   ...
+    /// } == B + A # <- the finish shaping command with a target
+    /// ```
+    FinishShaping(Loc, Option<Expr>),
+    /// Simplify the shaped expression with the rules tagged `@simp`
+    ///
+    /// Example:
+    /// ```noq
+    /// @simp sum_id :: 0 + A = A
+    ///
+    /// 0 + (0 + a) {
+    ///   simp # <- the simp command
+    /// }
+    /// ```
+    Simp(Loc),
+    /// Search for a shaping that turns one expression into another
+    ///
+    /// On success the found shaping is printed and put into the history
+    /// instead of the command itself.
+    ///
+    /// Example:
+    /// ```noq
+    /// prove 0 + (a + b) == b + a using sum_id, sum_comm depth 3
+    /// ```
+    Prove {
+        loc: Loc,
+        start: Expr,
+        goal: Expr,
+        /// All the existing rules are used if the rules are not specified
+        rule_names: Option<Vec<String>>,
+        depth: usize,
+    },
+    /// Change one of the limits of the rewriting
+    ///
+    /// Example:
+    /// ```noq
+    /// limit nodes 1000 # <- the limit command
+    /// ```
+    Limit(Loc, Limit, usize),
+    /// Choose what happens to the variables of the rule bodies that
+    /// don't appear in the heads: `error`, `warn` or `fresh`
+    ///
+    /// Example:
+    /// ```noq
+    /// unbound fresh # <- the unbound command
+    /// foo :: f(A) = g(A, B)
+    ///
+    /// f(a) {
+    ///   foo | all # <- B becomes the fresh symbol b_1
+    /// }
+    /// ```
+    Unbound(Loc, Unbound),
+    /// Undo previusly applied rule
+    ///
+    /// The undone step is removed from the history. Right after the
+    /// shaping is finished, undo reopens it and removes the rule it
+    /// defined.
+    ///
+    /// Example:
+    /// ```noq
+    /// A + B :: {
+    ///   ...
+    ///   undo   # <- the undo command
+    ///   undo 2 # <- undo the last two steps
+    ///   ...
+    /// }
+    /// ```
+    UndoRule(Loc, usize),
+    /// Redo previously undone steps
+    ///
+    /// Example:
+    /// ```noq
+    /// A + B :: {
+    ///   ...
+    ///   undo
+    ///   redo # <- the redo command
+    ///   ...
+    /// }
+    /// ```
+    Redo(Loc, usize),
     /// Quit command
     ///
     /// Example:
@@ -526,6 +2088,23 @@ enum Command {
     /// quit
     /// ```
     Quit,
+    /// List the rules sorted by name, optionally only the ones with the
+    /// head matching the pattern
+    ///
+    /// Example:
+    /// ```noq
+    /// rules                # <- the rules command
+    /// rules matching A + B # <- only the rules about sums
+    /// ```
+    ListRules(Loc, Option<Expr>),
+    /// Show the definition of the rule, where it was defined and the
+    /// shaping it was built with
+    ///
+    /// Example:
+    /// ```noq
+    /// show sum_comm # <- the show command
+    /// ```
+    ShowRule(Loc, String),
     /// Delete rule by name
     ///
     /// ```noq
@@ -535,13 +2114,24 @@ enum Command {
     DeleteRule(Loc, String),
     /// Load file
     ///
+    /// The rules of the file loaded `as` a module are named with the
+    /// name of the module in front of them.
+    ///
     /// ```noq
     /// load "std/std.noq" # <- the load command
+    /// load "std/std.noq" as std # <- defines `std.sum_comm` and others
     ///
     /// (a - a) + b {
     ///   ...
     /// ```
-    Load(Loc, String),
+    Load(Loc, String, Option<String>),
+    /// Make the rules of the module available without its name in front
+    ///
+    /// ```noq
+    /// load "std/std.noq" as std
+    /// open std # <- the open command
+    /// ```
+    Open(Loc, String),
     /// Save file
     ///
     /// ```noq
@@ -553,15 +2143,269 @@ enum Command {
 }
 
 impl Command {
+    /// Parses everything after the `|` of the apply rule command: the
+    /// optional `!`, the name of the strategy and the traversal modifiers
+    fn parse_strategy(
+        lexer: &mut Lexer<impl Iterator<Item = char>>,
+    ) -> Result<(bool, Token, Traversal), CommandSyntaxError> {
+        let (reversed, strategy_name_token) = {
+            let token = lexer.next_token();
+            if token.kind == TokenKind::Bang {
+                (
+                    true,
+                    lexer
+                        .expect_token(TokenKind::Ident)
+                        .map_err(CommandSyntaxError::StrategyName)?,
+                )
+            } else if token.kind == TokenKind::Ident {
+                (false, token)
+            } else {
+                return Err(CommandSyntaxError::StrategyName(token));
+            }
+        };
+
+        let traversal = Self::parse_traversal(lexer)?;
+        Ok((reversed, strategy_name_token, traversal))
+    }
+
+    /// Parses the order and the direction of the traversal
+    fn parse_traversal(
+        lexer: &mut Lexer<impl Iterator<Item = char>>,
+    ) -> Result<Traversal, CommandSyntaxError> {
+        let mut traversal = Traversal::default();
+        let mut order_token: Option<Token> = None;
+        let mut direction_token: Option<Token> = None;
+        while lexer.peek_token().kind == TokenKind::Ident {
+            let text = lexer.peek_token().text.clone();
+            if let Some(order) = Order::by_name(&text) {
+                let token = lexer.next_token();
+                if order_token.is_some() {
+                    return Err(CommandSyntaxError::StrategyModifier(token));
+                }
+                traversal.order = order;
+                order_token = Some(token);
+            } else if let Some(right_to_left) = Traversal::direction_by_name(&text) {
+                let token = lexer.next_token();
+                if direction_token.is_some() {
+                    return Err(CommandSyntaxError::StrategyModifier(token));
+                }
+                traversal.right_to_left = right_to_left;
+                direction_token = Some(token);
+            } else {
+                break;
+            }
+        }
+
+        Ok(traversal)
+    }
+
+    /// Name and arguments of the applied rule written as `name` or
+    /// `name(arg, ...)`
+    fn applied_rule_name(expr: &Expr) -> Option<(String, Vec<Expr>)> {
+        match expr {
+            Expr::Sym(name) => Some((name.clone(), vec![])),
+            Expr::Fun(head, args) => match &**head {
+                Expr::Sym(name) => Some((name.clone(), args.clone())),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    /// Parses the optional symbols that become variables of the rule
+    /// defined via shaping like `forall a, b.`. The dot sticks to the last
+    /// symbol, since it may be a part of the name. The text of the
+    /// returned tokens is the symbol without the dot.
+    fn parse_forall(
+        lexer: &mut Lexer<impl Iterator<Item = char>>,
+    ) -> Result<Vec<Token>, CommandSyntaxError> {
+        let mut names = Vec::new();
+        // `forall(...)` is a functor
+        let token = lexer.peek_token();
+        if token.kind != TokenKind::Ident
+            || token.text != "forall"
+            || lexer.peek_next_token().kind != TokenKind::Ident
+        {
+            return Ok(names);
+        }
+        lexer.next_token();
+        loop {
+            let token = lexer.next_token();
+            if token.kind != TokenKind::Ident {
+                return Err(CommandSyntaxError::ForallName(token));
+            }
+            let (name, last) = match token.text.strip_suffix('.') {
+                Some(name) => (name.to_string(), true),
+                None => (token.text.clone(), false),
+            };
+            match name.chars().next() {
+                Some(x) if x.is_lowercase() => names.push(Token {
+                    text: name,
+                    ..token
+                }),
+                _ => return Err(CommandSyntaxError::ForallName(token)),
+            }
+            if last {
+                return Ok(names);
+            }
+            // The dot separated from the last symbol
+            let next = lexer.peek_token();
+            if next.kind == TokenKind::Ident && next.text == "." {
+                lexer.next_token();
+                return Ok(names);
+            }
+            lexer
+                .expect_token(TokenKind::Comma)
+                .map_err(CommandSyntaxError::ForallSep)?;
+        }
+    }
+
+    /// Checks that the symbols of `forall` appear in the head of the rule
+    fn forall_names(forall: Vec<Token>, head: &Expr) -> Result<Vec<String>, CommandSyntaxError> {
+        let syms = head.syms();
+        forall
+            .into_iter()
+            .map(|token| {
+                if syms.contains(&token.text) {
+                    Ok(token.text)
+                } else {
+                    Err(CommandSyntaxError::ForallNotInHead(token, head.clone()))
+                }
+            })
+            .collect()
+    }
+
+    /// Parses the optional bindings of the variables of the applied rule
+    /// like `[A := x, B := y]`
+    fn parse_bindings(
+        lexer: &mut Lexer<impl Iterator<Item = char>>,
+    ) -> Result<Vec<(String, Expr)>, CommandSyntaxError> {
+        let mut bindings = Vec::new();
+        if lexer.peek_token().kind != TokenKind::OpenBracket {
+            return Ok(bindings);
+        }
+        lexer.next_token();
+        loop {
+            let token = lexer.next_token();
+            let name = match token.kind {
+                TokenKind::Ident => match Expr::var_or_sym_based_on_name(&token.text) {
+                    Expr::Var(name) => name,
+                    _ => return Err(CommandSyntaxError::BindingName(token)),
+                },
+                _ => return Err(CommandSyntaxError::BindingName(token)),
+            };
+            lexer
+                .expect_token(TokenKind::ColonEquals)
+                .map_err(CommandSyntaxError::BindingSep)?;
+            let value = Expr::parse(lexer).map_err(CommandSyntaxError::BindingValue)?;
+            bindings.push((name, value));
+            let token = lexer.next_token();
+            match token.kind {
+                TokenKind::Comma => {}
+                TokenKind::CloseBracket => break,
+                _ => return Err(CommandSyntaxError::BindingsEnd(token)),
+            }
+        }
+        Ok(bindings)
+    }
+
+    /// Parses the name of the tag after `@`
+    fn parse_tag_name(
+        lexer: &mut Lexer<impl Iterator<Item = char>>,
+    ) -> Result<String, CommandSyntaxError> {
+        lexer
+            .expect_token(TokenKind::Ident)
+            .map(|token| token.text)
+            .map_err(CommandSyntaxError::TagName)
+    }
+
+    /// Parses the optional amount of repetitions after `undo` or `redo`.
+    /// The amount must be on the same line as the keyword, otherwise a
+    /// command that starts with a number would be taken for it.
+    fn parse_repeat_count(
+        lexer: &mut Lexer<impl Iterator<Item = char>>,
+        keyword: &Token,
+    ) -> Result<usize, CommandSyntaxError> {
+        let token = lexer.peek_token();
+        if token.kind != TokenKind::Ident || token.loc.row != keyword.loc.row {
+            return Ok(1);
+        }
+        if !token.text.chars().all(|x| x.is_ascii_digit()) {
+            return Ok(1);
+        }
+        let token = lexer.next_token();
+        match token.text.parse() {
+            Ok(count) if count > 0 => Ok(count),
+            _ => Err(CommandSyntaxError::RepeatCount(token)),
+        }
+    }
+
+    /// The word that starts the command, if the command starts with one
+    /// of [`COMMAND_WORDS`]. The words are not reserved: followed by
+    /// something that continues an expression on the same line, like
+    /// `show(X)` or `data :: ...`, the word is a symbol.
+    fn command_word(lexer: &mut Lexer<impl Iterator<Item = char>>) -> Option<&'static str> {
+        let token = lexer.peek_token();
+        if token.kind != TokenKind::Ident {
+            return None;
+        }
+        let word = *COMMAND_WORDS.iter().find(|word| **word == token.text)?;
+        let row = token.loc.row;
+        let end_col = token.loc.col + token.text.chars().count();
+        let next = lexer.peek_next_token();
+        let continues_expr = next.loc.row == row
+            && match next.kind {
+                // Only `show(X)` is a functor, `show (X)` is not
+                TokenKind::OpenParen => next.loc.col == end_col,
+                kind => {
+                    Op::from_token_kind(kind).is_some()
+                        || matches!(
+                            kind,
+                            TokenKind::DoubleColon
+                                | TokenKind::OpenCurly
+                                | TokenKind::Bar
+                                | TokenKind::Question
+                                | TokenKind::OpenBracket
+                                | TokenKind::Equals
+                                | TokenKind::Colon
+                        )
+                }
+            };
+        if continues_expr {
+            None
+        } else {
+            Some(word)
+        }
+    }
+
     fn parse(lexer: &mut Lexer<impl Iterator<Item = char>>) -> Result<Command, CommandSyntaxError> {
         let keyword_kind = lexer.peek_token().kind;
+        let word = Self::command_word(lexer);
         match keyword_kind {
             TokenKind::Load => {
                 lexer.next_token();
                 let token = lexer
                     .expect_token(TokenKind::Str)
                     .map_err(CommandSyntaxError::LoadArg)?;
-                Ok(Self::Load(token.loc, token.text))
+                let mut module = None;
+                if lexer.peek_token().kind == TokenKind::Ident && lexer.peek_token().text == "as" {
+                    lexer.next_token();
+                    module = Some(
+                        lexer
+                            .expect_token(TokenKind::Ident)
+                            .map_err(CommandSyntaxError::ModuleName)?
+                            .text,
+                    );
+                }
+                Ok(Self::Load(token.loc, token.text, module))
+            }
+            TokenKind::Ident if word == Some("open") => {
+                let keyword = lexer.next_token();
+                let module = lexer
+                    .expect_token(TokenKind::Ident)
+                    .map_err(CommandSyntaxError::ModuleName)?
+                    .text;
+                Ok(Self::Open(keyword.loc, module))
             }
             TokenKind::Save => {
                 lexer.next_token();
@@ -572,16 +2416,408 @@ impl Command {
             }
             TokenKind::CloseCurly => {
                 let keyword = lexer.next_token();
-                Ok(Command::FinishShaping(keyword.loc))
+                let mut target = None;
+                if lexer.peek_token().kind == TokenKind::EqualsEquals {
+                    lexer.next_token();
+                    // The target ends with its line, since the next command
+                    // may start with an open paren
+                    target =
+                        Some(Expr::parse_line(lexer).map_err(CommandSyntaxError::ShapingTarget)?);
+                }
+                Ok(Command::FinishShaping(keyword.loc, target))
+            }
+            TokenKind::Ident if word == Some("theory") => {
+                let keyword = lexer.next_token();
+                let token = lexer.next_token();
+                if token.kind != TokenKind::Ident || token.text != "ac" {
+                    return Err(CommandSyntaxError::TheoryName(token));
+                }
+                let mut ops = Vec::new();
+                loop {
+                    let token = lexer.next_token();
+                    match Op::from_token_kind(token.kind) {
+                        Some(op) => ops.push(op),
+                        None => return Err(CommandSyntaxError::TheoryOperator(token)),
+                    }
+                    if lexer.peek_token().kind != TokenKind::Comma {
+                        break;
+                    }
+                    lexer.next_token();
+                }
+                Ok(Command::Theory(keyword.loc, ops))
+            }
+            TokenKind::Ident if word == Some("focus") => {
+                let keyword = lexer.next_token();
+                let token = lexer.next_token();
+                let path: Option<Vec<usize>> = match token.kind {
+                    TokenKind::Ident => token
+                        .text
+                        .split('.')
+                        .map(|index| index.parse().ok())
+                        .collect(),
+                    _ => None,
+                };
+                let path = path.ok_or(CommandSyntaxError::FocusPath(token))?;
+                lexer
+                    .expect_token(TokenKind::OpenCurly)
+                    .map_err(CommandSyntaxError::FocusWithoutShaping)?;
+                Ok(Command::Focus(keyword.loc, path))
+            }
+            TokenKind::Ident if word == Some("cases") => {
+                let keyword = lexer.next_token();
+                let subject = Expr::parse(lexer).map_err(CommandSyntaxError::CasesSubject)?;
+                let token = lexer.next_token();
+                if token.kind != TokenKind::Ident || token.text != "of" {
+                    return Err(CommandSyntaxError::CasesOf(token));
+                }
+                let mut patterns =
+                    vec![Expr::parse(lexer).map_err(CommandSyntaxError::CasePattern)?];
+                while lexer.peek_token().kind == TokenKind::Bar {
+                    lexer.next_token();
+                    patterns.push(Expr::parse(lexer).map_err(CommandSyntaxError::CasePattern)?);
+                }
+                Ok(Command::Cases {
+                    loc: keyword.loc,
+                    subject,
+                    patterns,
+                })
+            }
+            TokenKind::Ident if word == Some("induction") => {
+                let keyword = lexer.next_token();
+                let subject = Expr::parse(lexer).map_err(CommandSyntaxError::InductionSubject)?;
+                let token = lexer.next_token();
+                if token.kind != TokenKind::Ident || token.text != "on" {
+                    return Err(CommandSyntaxError::InductionOn(token));
+                }
+                let data = lexer
+                    .expect_token(TokenKind::Ident)
+                    .map_err(CommandSyntaxError::InductionData)?
+                    .text;
+                Ok(Command::Induction {
+                    loc: keyword.loc,
+                    subject,
+                    data,
+                })
+            }
+            TokenKind::Ident if word == Some("generalize") => {
+                let keyword = lexer.next_token();
+                let first = Expr::parse(lexer).map_err(CommandSyntaxError::GeneralizeExpr)?;
+                lexer
+                    .expect_token(TokenKind::Comma)
+                    .map_err(CommandSyntaxError::GeneralizeSep)?;
+                let second = Expr::parse(lexer).map_err(CommandSyntaxError::GeneralizeExpr)?;
+                Ok(Command::Generalize(keyword.loc, first, second))
+            }
+            TokenKind::Ident if word == Some("data") => {
+                let keyword = lexer.next_token();
+                let name = lexer
+                    .expect_token(TokenKind::Ident)
+                    .map_err(CommandSyntaxError::DataName)?
+                    .text;
+                let mut separator = lexer
+                    .expect_token(TokenKind::Equals)
+                    .map_err(CommandSyntaxError::DataSep)?;
+                let mut constructors = vec![];
+                loop {
+                    let constructor =
+                        Expr::parse(lexer).map_err(CommandSyntaxError::DataConstructor)?;
+                    let well_formed = match &constructor {
+                        Expr::Sym(_) => true,
+                        Expr::Fun(head, args) => {
+                            matches!(**head, Expr::Sym(_))
+                                && args.iter().all(|arg| matches!(arg, Expr::Sym(_)))
+                        }
+                        _ => false,
+                    };
+                    if !well_formed {
+                        return Err(CommandSyntaxError::DataConstructorShape(
+                            separator,
+                            constructor,
+                        ));
+                    }
+                    constructors.push(constructor);
+                    if lexer.peek_token().kind != TokenKind::Bar {
+                        break;
+                    }
+                    separator = lexer.next_token();
+                }
+                Ok(Command::Data {
+                    loc: keyword.loc,
+                    name,
+                    constructors,
+                })
+            }
+            TokenKind::Ident if word == Some("lemma") => {
+                let keyword = lexer.next_token();
+                let name = lexer
+                    .expect_token(TokenKind::Ident)
+                    .map_err(CommandSyntaxError::LemmaName)?
+                    .text;
+                lexer
+                    .expect_token(TokenKind::DoubleColon)
+                    .map_err(CommandSyntaxError::DefineRuleSep)?;
+                let forall = Self::parse_forall(lexer)?;
+                let (head, target) =
+                    match Expr::parse(lexer).map_err(CommandSyntaxError::LemmaEquation)? {
+                        Expr::Op(Op::Eql, head, target) => (*head, *target),
+                        _ => return Err(CommandSyntaxError::LemmaNotEquation(keyword)),
+                    };
+                let forall = Self::forall_names(forall, &head)?;
+                lexer
+                    .expect_token(TokenKind::OpenCurly)
+                    .map_err(CommandSyntaxError::LemmaWithoutShaping)?;
+                Ok(Command::DefineRuleViaShaping {
+                    name,
+                    expr: head,
+                    target: Some(target),
+                    forall,
+                    tags: vec![],
+                    redefine: false,
+                })
             }
             TokenKind::Undo => {
                 let keyword = lexer.next_token();
-                Ok(Command::UndoRule(keyword.loc))
+                let count = Self::parse_repeat_count(lexer, &keyword)?;
+                Ok(Command::UndoRule(keyword.loc, count))
+            }
+            TokenKind::Ident if word == Some("redo") => {
+                let keyword = lexer.next_token();
+                let count = Self::parse_repeat_count(lexer, &keyword)?;
+                Ok(Command::Redo(keyword.loc, count))
+            }
+            TokenKind::Ident if word == Some("simp") => {
+                let keyword = lexer.next_token();
+                Ok(Command::Simp(keyword.loc))
+            }
+            TokenKind::Ident if word == Some("prove") => {
+                let keyword = lexer.next_token();
+                let (start, goal) =
+                    match Expr::parse(lexer).map_err(CommandSyntaxError::ProveEquation)? {
+                        Expr::Op(Op::Eql, start, goal) => (*start, *goal),
+                        _ => return Err(CommandSyntaxError::ProveNotEquation(keyword)),
+                    };
+                let mut rule_names = None;
+                if lexer.peek_token().kind == TokenKind::Ident && lexer.peek_token().text == "using"
+                {
+                    lexer.next_token();
+                    let mut names = vec![
+                        lexer
+                            .expect_token(TokenKind::Ident)
+                            .map_err(CommandSyntaxError::ProveRuleName)?
+                            .text,
+                    ];
+                    while lexer.peek_token().kind == TokenKind::Comma {
+                        lexer.next_token();
+                        names.push(
+                            lexer
+                                .expect_token(TokenKind::Ident)
+                                .map_err(CommandSyntaxError::ProveRuleName)?
+                                .text,
+                        );
+                    }
+                    rule_names = Some(names);
+                }
+                let mut depth = DEFAULT_PROVE_DEPTH;
+                if lexer.peek_token().kind == TokenKind::Ident && lexer.peek_token().text == "depth"
+                {
+                    lexer.next_token();
+                    let token = lexer.next_token();
+                    depth = match token.text.parse() {
+                        Ok(depth) if token.kind == TokenKind::Ident => depth,
+                        _ => return Err(CommandSyntaxError::ProveDepth(token)),
+                    };
+                }
+                Ok(Command::Prove {
+                    loc: keyword.loc,
+                    start,
+                    goal,
+                    rule_names,
+                    depth,
+                })
+            }
+            TokenKind::Ident if word == Some("limit") => {
+                let keyword = lexer.next_token();
+                let limit = {
+                    let token = lexer.next_token();
+                    match Limit::by_name(&token.text) {
+                        Some(limit) if token.kind == TokenKind::Ident => limit,
+                        _ => return Err(CommandSyntaxError::LimitName(token)),
+                    }
+                };
+                let value = {
+                    let token = lexer.next_token();
+                    match token.text.parse() {
+                        Ok(value) if token.kind == TokenKind::Ident && value > 0 => value,
+                        _ => return Err(CommandSyntaxError::LimitValue(token)),
+                    }
+                };
+                Ok(Command::Limit(keyword.loc, limit, value))
+            }
+            TokenKind::Ident if word == Some("unbound") => {
+                let keyword = lexer.next_token();
+                let token = lexer.next_token();
+                match Unbound::by_name(&token.text) {
+                    Some(unbound) if token.kind == TokenKind::Ident => {
+                        Ok(Command::Unbound(keyword.loc, unbound))
+                    }
+                    _ => Err(CommandSyntaxError::UnboundValue(token)),
+                }
+            }
+            TokenKind::At => {
+                let at = lexer.next_token();
+                let mut tags = vec![Self::parse_tag_name(lexer)?];
+                while lexer.peek_token().kind == TokenKind::At {
+                    lexer.next_token();
+                    tags.push(Self::parse_tag_name(lexer)?);
+                }
+                match Self::parse(lexer)? {
+                    Command::DefineRule {
+                        loc,
+                        name,
+                        rule,
+                        redefine,
+                        ..
+                    } => Ok(Command::DefineRule {
+                        loc,
+                        name,
+                        rule,
+                        tags,
+                        redefine,
+                    }),
+                    Command::DefineRuleViaShaping {
+                        name,
+                        expr,
+                        target,
+                        forall,
+                        redefine,
+                        ..
+                    } => Ok(Command::DefineRuleViaShaping {
+                        name,
+                        expr,
+                        target,
+                        forall,
+                        tags,
+                        redefine,
+                    }),
+                    Command::DefineDerivedRule {
+                        loc,
+                        name,
+                        derivation,
+                        redefine,
+                        ..
+                    } => Ok(Command::DefineDerivedRule {
+                        loc,
+                        name,
+                        derivation,
+                        tags,
+                        redefine,
+                    }),
+                    _ => Err(CommandSyntaxError::TaggedCommand(at)),
+                }
+            }
+            TokenKind::Ident if word == Some("redefine") => {
+                let keyword = lexer.next_token();
+                match Self::parse(lexer)? {
+                    Command::DefineRule {
+                        loc,
+                        name,
+                        rule,
+                        tags,
+                        ..
+                    } => Ok(Command::DefineRule {
+                        loc,
+                        name,
+                        rule,
+                        tags,
+                        redefine: true,
+                    }),
+                    Command::DefineRuleViaShaping {
+                        name,
+                        expr,
+                        target,
+                        forall,
+                        tags,
+                        ..
+                    } => Ok(Command::DefineRuleViaShaping {
+                        name,
+                        expr,
+                        target,
+                        forall,
+                        tags,
+                        redefine: true,
+                    }),
+                    Command::DefineDerivedRule {
+                        loc,
+                        name,
+                        derivation,
+                        tags,
+                        ..
+                    } => Ok(Command::DefineDerivedRule {
+                        loc,
+                        name,
+                        derivation,
+                        tags,
+                        redefine: true,
+                    }),
+                    _ => Err(CommandSyntaxError::RedefinedCommand(keyword)),
+                }
+            }
+            TokenKind::Ident if word == Some("assume") => {
+                let keyword = lexer.next_token();
+                match Self::parse(lexer)? {
+                    Command::DefineRule {
+                        loc,
+                        name,
+                        rule,
+                        tags,
+                        redefine: false,
+                    } if tags.is_empty() => Ok(Command::Assume { loc, name, rule }),
+                    _ => Err(CommandSyntaxError::AssumedCommand(keyword)),
+                }
             }
             TokenKind::Quit => {
                 lexer.next_token();
                 Ok(Command::Quit)
             }
+            TokenKind::Ident if word == Some("rules") => {
+                let keyword = lexer.next_token();
+                let mut pattern = None;
+                if lexer.peek_token().kind == TokenKind::Ident
+                    && lexer.peek_token().text == "matching"
+                {
+                    lexer.next_token();
+                    pattern = Some(Expr::parse(lexer).map_err(CommandSyntaxError::RulesPattern)?);
+                }
+                Ok(Command::ListRules(keyword.loc, pattern))
+            }
+            TokenKind::Ident if word == Some("matches") => {
+                let keyword = lexer.next_token();
+                let name = lexer
+                    .expect_token(TokenKind::Ident)
+                    .map_err(CommandSyntaxError::MatchesArg)?
+                    .text;
+                let bindings = Self::parse_bindings(lexer)?;
+                let traversal = Self::parse_traversal(lexer)?;
+                Ok(Command::Matches {
+                    loc: keyword.loc.clone(),
+                    traversal,
+                    applied_rule: AppliedRule::ByName {
+                        loc: keyword.loc,
+                        name,
+                        args: vec![],
+                        reversed: false,
+                        bindings,
+                    },
+                })
+            }
+            TokenKind::Ident if word == Some("show") => {
+                let keyword = lexer.next_token();
+                let name = lexer
+                    .expect_token(TokenKind::Ident)
+                    .map_err(CommandSyntaxError::ShowArg)?
+                    .text;
+                Ok(Command::ShowRule(keyword.loc, name))
+            }
             TokenKind::Delete => {
                 let keyword = lexer.next_token();
                 let name = lexer
@@ -592,37 +2828,57 @@ impl Command {
             }
             _ => {
                 let expr = Expr::parse(lexer).map_err(CommandSyntaxError::CommandStart)?;
+                let applied_rule_name = Self::applied_rule_name(&expr);
+                let bindings = match applied_rule_name {
+                    Some(_) => Self::parse_bindings(lexer)?,
+                    None => vec![],
+                };
+                if !bindings.is_empty()
+                    && !matches!(
+                        lexer.peek_token().kind,
+                        TokenKind::Bar | TokenKind::Question
+                    )
+                {
+                    return Err(CommandSyntaxError::BoundCommand(lexer.next_token()));
+                }
 
                 match lexer.peek_token().kind {
+                    TokenKind::Question if applied_rule_name.is_some() => {
+                        let question = lexer.next_token();
+                        let traversal = Self::parse_traversal(lexer)?;
+                        let (name, args) =
+                            applied_rule_name.expect("The name of the rule was checked above");
+                        Ok(Command::Matches {
+                            loc: question.loc.clone(),
+                            traversal,
+                            applied_rule: AppliedRule::ByName {
+                                loc: question.loc,
+                                name,
+                                args,
+                                reversed: false,
+                                bindings,
+                            },
+                        })
+                    }
                     TokenKind::Bar => {
                         let bar = lexer.next_token();
-                        let (reversed, strategy_name_token) = {
-                            let token = lexer.next_token();
-                            if token.kind == TokenKind::Bang {
-                                (
-                                    true,
-                                    lexer
-                                        .expect_token(TokenKind::Ident)
-                                        .map_err(CommandSyntaxError::StrategyName)?,
-                                )
-                            } else if token.kind == TokenKind::Ident {
-                                (false, token)
-                            } else {
-                                return Err(CommandSyntaxError::StrategyName(token));
-                            }
-                        };
-                        if let Expr::Sym(rule_name) = expr {
-                            Ok(Command::ApplyRule {
+                        let (reversed, strategy_name_token, traversal) =
+                            Self::parse_strategy(lexer)?;
+                        match applied_rule_name {
+                            Some((name, args)) => Ok(Command::ApplyRule {
                                 loc: bar.loc.clone(),
                                 strategy_name: strategy_name_token.text,
+                                traversal,
                                 applied_rule: AppliedRule::ByName {
                                     loc: bar.loc,
-                                    name: rule_name,
+                                    name,
+                                    args,
                                     reversed,
+                                    bindings,
                                 },
-                            })
-                        } else {
-                            todo!("Report applied rule must by symbol")
+                                side: None,
+                            }),
+                            None => Err(CommandSyntaxError::AppliedRuleName(bar, expr)),
                         }
                     }
                     TokenKind::Equals => {
@@ -630,27 +2886,28 @@ impl Command {
                         let equals = lexer.next_token();
                         let body =
                             Expr::parse(lexer).map_err(CommandSyntaxError::AnonymousRuleBody)?;
+                        if lexer.peek_token().kind == TokenKind::Question {
+                            let question = lexer.next_token();
+                            let traversal = Self::parse_traversal(lexer)?;
+                            return Ok(Command::Matches {
+                                loc: question.loc,
+                                traversal,
+                                applied_rule: AppliedRule::Anonymous {
+                                    loc: equals.loc,
+                                    head,
+                                    body,
+                                },
+                            });
+                        }
                         lexer
                             .expect_token(TokenKind::Bar)
                             .map_err(CommandSyntaxError::AnonymousRuleWithoutStrategy)?;
-                        let (reversed, strategy_name_token) = {
-                            let token = lexer.next_token();
-                            if token.kind == TokenKind::Bang {
-                                (
-                                    true,
-                                    lexer
-                                        .expect_token(TokenKind::Ident)
-                                        .map_err(CommandSyntaxError::StrategyName)?,
-                                )
-                            } else if token.kind == TokenKind::Ident {
-                                (false, token)
-                            } else {
-                                return Err(CommandSyntaxError::StrategyName(token));
-                            }
-                        };
+                        let (reversed, strategy_name_token, traversal) =
+                            Self::parse_strategy(lexer)?;
                         Ok(Command::ApplyRule {
                             loc: equals.loc.clone(),
                             strategy_name: strategy_name_token.text,
+                            traversal,
                             applied_rule: if reversed {
                                 AppliedRule::Anonymous {
                                     loc: equals.loc,
@@ -664,41 +2921,158 @@ impl Command {
                                     body,
                                 }
                             },
+                            side: None,
                         })
                     }
+                    TokenKind::Colon => {
+                        let colon = lexer.next_token();
+                        let side = match &expr {
+                            Expr::Sym(name) => Side::by_name(name),
+                            _ => None,
+                        };
+                        let side =
+                            side.ok_or_else(|| CommandSyntaxError::SideName(colon.clone()))?;
+                        match Self::parse(lexer)? {
+                            Command::ApplyRule {
+                                loc,
+                                strategy_name,
+                                traversal,
+                                applied_rule,
+                                ..
+                            } => Ok(Command::ApplyRule {
+                                loc,
+                                strategy_name,
+                                traversal,
+                                applied_rule,
+                                side: Some(side),
+                            }),
+                            _ => Err(CommandSyntaxError::SideCommand(colon)),
+                        }
+                    }
                     TokenKind::OpenCurly => {
                         let keyword = lexer.next_token();
                         Ok(Command::StartShaping(keyword.loc, expr))
                     }
                     TokenKind::DoubleColon => {
                         let keyword = lexer.next_token();
-                        match expr {
-                            Expr::Sym(name) => {
+                        let (name, params) = match expr {
+                            Expr::Sym(name) => (Some(name), vec![]),
+                            Expr::Fun(head, args) => match *head {
+                                Expr::Sym(name) => {
+                                    let mut params = Vec::new();
+                                    for arg in args {
+                                        match arg {
+                                            Expr::Var(param) => params.push(param),
+                                            arg => {
+                                                return Err(CommandSyntaxError::RuleParam(
+                                                    keyword, arg,
+                                                ))
+                                            }
+                                        }
+                                    }
+                                    (Some(name), params)
+                                }
+                                _ => (None, vec![]),
+                            },
+                            _ => (None, vec![]),
+                        };
+                        match name {
+                            Some(name) => {
+                                let forall = Self::parse_forall(lexer)?;
                                 let head = Expr::parse(lexer)
                                     .map_err(CommandSyntaxError::DefineRuleHead)?;
+                                if !forall.is_empty()
+                                    && lexer.peek_token().kind != TokenKind::OpenCurly
+                                {
+                                    return Err(CommandSyntaxError::ForallWithoutShaping(
+                                        lexer.next_token(),
+                                    ));
+                                }
                                 match lexer.peek_token().kind {
+                                    TokenKind::OpenCurly if !params.is_empty() => {
+                                        Err(CommandSyntaxError::ParameterizedShaping(
+                                            lexer.next_token(),
+                                        ))
+                                    }
                                     TokenKind::OpenCurly => {
+                                        let forall = Self::forall_names(forall, &head)?;
                                         lexer.next_token();
-                                        Ok(Command::DefineRuleViaShaping { name, expr: head })
+                                        Ok(Command::DefineRuleViaShaping {
+                                            name,
+                                            expr: head,
+                                            target: None,
+                                            forall,
+                                            tags: vec![],
+                                            redefine: false,
+                                        })
                                     }
                                     TokenKind::Equals => {
                                         lexer.next_token();
-                                        let body = Expr::parse(lexer)
+                                        // The body ends with its line, since the
+                                        // next command may start with an open paren
+                                        let body = Expr::parse_line(lexer)
                                             .map_err(CommandSyntaxError::DefineRuleBody)?;
-                                        Ok(Command::DefineRule(
-                                            keyword.loc.clone(),
-                                            name,
-                                            Rule::User {
-                                                loc: keyword.loc,
+                                        let mut clauses = vec![Rule::User {
+                                            loc: keyword.loc.clone(),
+                                            head,
+                                            body,
+                                            params: params.clone(),
+                                        }];
+                                        while lexer.peek_token().kind == TokenKind::Bar {
+                                            let bar = lexer.next_token();
+                                            let head = Expr::parse(lexer)
+                                                .map_err(CommandSyntaxError::DefineRuleHead)?;
+                                            lexer
+                                                .expect_token(TokenKind::Equals)
+                                                .map_err(CommandSyntaxError::DefineRuleSep)?;
+                                            let body = Expr::parse_line(lexer)
+                                                .map_err(CommandSyntaxError::DefineRuleBody)?;
+                                            clauses.push(Rule::User {
+                                                loc: bar.loc,
                                                 head,
                                                 body,
-                                            },
-                                        ))
+                                                params: params.clone(),
+                                            });
+                                        }
+                                        Ok(Command::DefineRule {
+                                            loc: keyword.loc,
+                                            name,
+                                            rule: Rule::from_clauses(clauses),
+                                            tags: vec![],
+                                            redefine: false,
+                                        })
                                     }
-                                    _ => Err(CommandSyntaxError::DefineRuleSep(lexer.next_token())),
+                                    _ => match Derivation::from_expr(&head) {
+                                        Some(mut derivation) if params.is_empty() => {
+                                            while lexer.peek_token().kind == TokenKind::Semicolon {
+                                                let semicolon = lexer.next_token();
+                                                let step = Expr::parse(lexer)
+                                                    .map_err(CommandSyntaxError::DerivationStep)?;
+                                                let step = Derivation::from_expr(&step).ok_or(
+                                                    CommandSyntaxError::DerivedRule(
+                                                        semicolon, step,
+                                                    ),
+                                                )?;
+                                                derivation = Derivation::Seq(
+                                                    Box::new(derivation),
+                                                    Box::new(step),
+                                                );
+                                            }
+                                            Ok(Command::DefineDerivedRule {
+                                                loc: keyword.loc,
+                                                name,
+                                                derivation,
+                                                tags: vec![],
+                                                redefine: false,
+                                            })
+                                        }
+                                        _ => Err(CommandSyntaxError::DefineRuleSep(
+                                            lexer.next_token(),
+                                        )),
+                                    },
                                 }
                             }
-                            _ => todo!("Report that we expected a symbol"),
+                            None => todo!("Report that we expected a symbol"),
                         }
                     }
                     _ => Err(CommandSyntaxError::CommandSep(lexer.next_token())),
@@ -706,259 +3080,2349 @@ impl Command {
             }
         }
     }
-}
+}
+
+/// Words that start the commands when they are not a part of an
+/// expression, see [`Command::command_word`]
+const COMMAND_WORDS: &[&str] = &[
+    "limit",
+    "rules",
+    "show",
+    "open",
+    "cases",
+    "data",
+    "induction",
+    "simp",
+    "prove",
+    "redo",
+    "matches",
+    "lemma",
+    "redefine",
+    "focus",
+    "theory",
+    "assume",
+    "unbound",
+    "generalize",
+];
+
+/// Maximum amount of steps the `prove` command looks for when the depth
+/// is not specified
+const DEFAULT_PROVE_DEPTH: usize = 4;
+
+/// A step of the shaping that can be undone
+struct ShapingStep {
+    /// The expression before the step
+    previous_expr: Expr,
+    /// The goals before the step
+    previous_goals: Goals,
+    command: Command,
+    /// Position of the command in [`Context::history`]. It's absent for
+    /// the steps made by the loaded files, since only the `load` itself
+    /// is in the history.
+    index: Option<usize>,
+}
+
+/// A rule removed from the [`Context`] along with its tags and the
+/// commands it was built with
+type TakenRule = (Rule, Option<Vec<String>>, Option<Vec<Command>>);
+
+/// The rule that is defined by a shaping once it's finished
+struct RuleViaShaping {
+    name: String,
+    head: Expr,
+    forall: Vec<String>,
+    tags: Vec<String>,
+    redefine: bool,
+}
+
+struct ShapingFrame {
+    expr: Expr,
+    history: Vec<ShapingStep>,
+    /// The rule defined when the shaping is finished
+    rule_via_shaping: Option<RuleViaShaping>,
+    /// The rule replaced by the redefinition when the shaping was
+    /// finished, so it's brought back if the finishing is undone
+    replaced_rule: Option<TakenRule>,
+    /// The expression the shaping must end with
+    target: Option<Expr>,
+    focus: Option<Focus>,
+    /// Rules assumed during the shaping. They are removed from the
+    /// rules of the context when the shaping is finished.
+    assumptions: Vec<(String, Rule)>,
+    /// The goals made by the `cases` steps. The shaped expression is
+    /// the active goal.
+    goals: Goals,
+}
+
+/// The subgoals of the shaped equation
+#[derive(Clone, Default)]
+struct Goals {
+    /// The case of the active goal, like `N := 0`
+    active: Option<String>,
+    /// Induction hypotheses of the active goal. They are among the rules
+    /// of the context only while the goal is active.
+    hypotheses: Vec<(String, Rule)>,
+    /// The goals to shape after the active one is closed
+    pending: Vec<Goal>,
+}
+
+/// The pattern of the case of the split goal along with the induction
+/// hypotheses of the case
+type Case = (Expr, Vec<(String, Rule)>);
+
+#[derive(Clone)]
+struct Goal {
+    case: String,
+    expr: Expr,
+    hypotheses: Vec<(String, Rule)>,
+}
+
+/// The shaping of a subexpression of the parent shaping
+struct Focus {
+    /// Location of the subexpression in the expression of the parent
+    path: Vec<usize>,
+    /// Position of the `focus` command in [`Context::history`]. It's
+    /// absent when the command comes from a loaded file.
+    opened_at: Option<usize>,
+    /// Expression of the parent before the result of the focused shaping
+    /// was put into it, so that can be undone
+    parent_expr: Option<Expr>,
+    /// Goals of the parent before the result was put into it
+    parent_goals: Goals,
+}
+
+impl ShapingFrame {
+    fn new(expr: Expr) -> Self {
+        Self {
+            expr,
+            history: Vec::new(),
+            rule_via_shaping: None,
+            replaced_rule: None,
+            target: None,
+            focus: None,
+            assumptions: Vec::new(),
+            goals: Goals::default(),
+        }
+    }
+
+    fn new_rule_via_shaping(rule: RuleViaShaping, target: Option<Expr>) -> Self {
+        Self {
+            expr: rule.head.clone(),
+            history: Vec::new(),
+            rule_via_shaping: Some(rule),
+            replaced_rule: None,
+            target,
+            focus: None,
+            assumptions: Vec::new(),
+            goals: Goals::default(),
+        }
+    }
+
+    fn new_focus(expr: Expr, focus: Focus) -> Self {
+        Self {
+            focus: Some(focus),
+            ..Self::new(expr)
+        }
+    }
+}
+
+struct Context {
+    rules: HashMap<String, Rule>,
+    /// Constructors of the declared data types by the type name
+    data_types: HashMap<String, Vec<Expr>>,
+    /// Tags of the rules by the rule name
+    rule_tags: HashMap<String, Vec<String>>,
+    /// Commands that built the rules by the rule name: either the whole
+    /// shaping from the opening command to the closing curly or the
+    /// derivation
+    rule_origins: HashMap<String, Vec<Command>>,
+    shaping_stack: Vec<ShapingFrame>,
+    /// Finished shaping frames along with the positions of their
+    /// finishing commands in the history, so finishing can be undone
+    finished_frames: Vec<(ShapingFrame, usize)>,
+    history: Vec<Command>,
+    /// Undone commands in the order they are redone
+    undone: Vec<Command>,
+    limits: Limits,
+    /// What happens to the variables of the rule bodies that don't
+    /// appear in the heads
+    unbound: Unbound,
+    /// Associative and commutative operators declared with `theory ac`
+    ac_ops: BTreeSet<Op>,
+    /// The file interpreted from the command line
+    main_file: Option<PathBuf>,
+    /// The files that are being loaded at the moment, outermost first.
+    /// The commands of the loaded files are not put into the history.
+    loading: Vec<PathBuf>,
+    /// How many of the outermost files in [`Context::loading`] are saved
+    /// sessions. The commands of a session are put into the history as
+    /// if they were typed, so its shapings can be continued and undone.
+    restoring: usize,
+    /// Every file that has been loaded along with the module it was
+    /// loaded into, so it is loaded only once
+    loaded: HashSet<(PathBuf, Option<String>)>,
+    /// The module of the file that is being loaded. Its rules are named
+    /// with the module name in front of them.
+    module: Option<String>,
+    /// All the loaded modules
+    modules: HashSet<String>,
+    /// The modules whose rules are available without the module name
+    opened: Vec<String>,
+    quit: bool,
+}
+
+/// Formats the path to a subexpression the way it's written in `focus`
+fn format_path(path: &[usize]) -> String {
+    let indices: Vec<String> = path.iter().map(|index| index.to_string()).collect();
+    indices.join(".")
+}
+
+fn pad(sink: &mut impl Write, width: usize) -> io::Result<()> {
+    write!(sink, "{:>width$}", "")
+}
+
+fn write_tags(sink: &mut impl Write, tags: &[String]) -> io::Result<()> {
+    for tag in tags {
+        write!(sink, "@{} ", tag)?
+    }
+    Ok(())
+}
+
+fn write_applied_rule_name(sink: &mut impl Write, name: &str, args: &[Expr]) -> io::Result<()> {
+    write!(sink, "{}", name)?;
+    if !args.is_empty() {
+        let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+        write!(sink, "({})", args.join(", "))?
+    }
+    Ok(())
+}
+
+fn write_bindings(sink: &mut impl Write, bindings: &[(String, Expr)]) -> io::Result<()> {
+    if bindings.is_empty() {
+        return Ok(());
+    }
+    let bindings: Vec<String> = bindings
+        .iter()
+        .map(|(name, value)| format!("{} := {}", name, value))
+        .collect();
+    write!(sink, "[{}]", bindings.join(", "))
+}
+
+/// Turns the symbols into the variables of the rule. The variable is
+/// the capitalized symbol, renamed if the rule already has it.
+fn abstract_symbols(names: &[String], head: Expr, body: Expr) -> (Expr, Expr) {
+    let mut taken = head.vars();
+    taken.extend(body.vars());
+    let (mut head, mut body) = (head, body);
+    for name in names {
+        let mut chars = name.chars();
+        let base: String = chars
+            .next()
+            .into_iter()
+            .flat_map(char::to_uppercase)
+            .chain(chars)
+            .collect();
+        let var = std::iter::once(base.clone())
+            .chain((1..).map(|index| format!("{}_{}", base, index)))
+            .find(|var| !taken.contains(var))
+            .expect("There are infinitely many candidates");
+        taken.insert(var.clone());
+        let (sym, var) = (Expr::Sym(name.clone()), Expr::Var(var));
+        head = head.replace(&sym, &var);
+        body = body.replace(&sym, &var);
+    }
+    (head, body)
+}
+
+/// Composes two [`Rule::User`] clauses: the result of the first one is
+/// unified with the head of the second one
+fn compose_clauses(first: &Rule, second: &Rule, loc: &Loc) -> Result<Rule, RuntimeError> {
+    let (
+        Rule::User {
+            head: head1,
+            body: body1,
+            ..
+        },
+        Rule::User {
+            head: head2,
+            body: body2,
+            ..
+        },
+    ) = (first, second)
+    else {
+        unreachable!("The derived rules are never built-in")
+    };
+    // The variables of the second rule are renamed, so they
+    // don't get mixed up with the variables of the first one
+    let mut taken = head1.vars();
+    taken.extend(body1.vars());
+    let second_vars = second.vars();
+    let clashing: Vec<String> = second_vars
+        .intersection(&taken)
+        .filter(|name| *name != "_")
+        .cloned()
+        .collect();
+    let mut renames = HashMap::new();
+    for name in clashing {
+        let fresh = (1..)
+            .map(|index| format!("{}_{}", name, index))
+            .find(|fresh| !taken.contains(fresh) && !second_vars.contains(fresh))
+            .expect("There are infinitely many candidates");
+        taken.insert(fresh.clone());
+        renames.insert(name, Expr::Var(fresh));
+    }
+    let head2 = head2.substitute(&renames);
+    let body2 = body2.substitute(&renames);
+    let bindings = match body1.unify(&head2) {
+        Some(bindings) => bindings,
+        None => {
+            return Err(RuntimeError::RulesDontCompose {
+                body: body1.clone(),
+                head: head2,
+                loc: loc.clone(),
+            })
+        }
+    };
+    Ok(Rule::User {
+        loc: loc.clone(),
+        head: head1.substitute(&bindings),
+        body: body2.substitute(&bindings),
+        params: vec![],
+    })
+}
+
+/// Writes the rule definition. The `column` is where the definition
+/// starts on the line, so the clauses after the first one line up with
+/// the `::`.
+fn write_rule(
+    sink: &mut impl Write,
+    name: &str,
+    rule: &Rule,
+    tags: &[String],
+    column: usize,
+) -> io::Result<()> {
+    write_tags(sink, tags)?;
+    let mut header = name.to_string();
+    let params = rule.params();
+    if !params.is_empty() {
+        header = format!("{}({})", header, params.join(", "));
+    }
+    match rule {
+        Rule::User { head, body, .. } => writeln!(sink, "{} :: {} = {}", header, head, body),
+        Rule::Clauses(clauses) => {
+            let column =
+                column + tags.iter().map(|tag| tag.len() + 2).sum::<usize>() + header.len() + 1;
+            for (index, clause) in clauses.iter().enumerate() {
+                if let Rule::User { head, body, .. } = clause {
+                    if index == 0 {
+                        writeln!(sink, "{} :: {} = {}", header, head, body)?
+                    } else {
+                        pad(sink, column)?;
+                        writeln!(sink, "|  {} = {}", head, body)?
+                    }
+                }
+            }
+            Ok(())
+        }
+        Rule::Replace => writeln!(sink, "{} :: <built-in>", name),
+    }
+}
+
+fn write_commands(sink: &mut impl Write, commands: &[Command]) -> io::Result<()> {
+    let mut indent = 0;
+    for command in commands {
+        match command {
+            Command::DefineRule {
+                name,
+                rule,
+                tags,
+                redefine,
+                ..
+            } => {
+                pad(sink, indent * 2)?;
+                write_tags(sink, tags)?;
+                if *redefine {
+                    write!(sink, "redefine ")?
+                }
+                let column = indent * 2
+                    + tags.iter().map(|tag| tag.len() + 2).sum::<usize>()
+                    + if *redefine { "redefine ".len() } else { 0 };
+                write_rule(sink, name, rule, &[], column)?
+            }
+            Command::DefineDerivedRule {
+                name,
+                derivation,
+                tags,
+                redefine,
+                ..
+            } => {
+                pad(sink, indent * 2)?;
+                write_tags(sink, tags)?;
+                if *redefine {
+                    write!(sink, "redefine ")?
+                }
+                writeln!(sink, "{} :: {}", name, derivation)?
+            }
+            Command::Assume { name, rule, .. } => {
+                pad(sink, indent * 2)?;
+                write!(sink, "assume ")?;
+                write_rule(sink, name, rule, &[], indent * 2 + "assume ".len())?
+            }
+            Command::DefineRuleViaShaping {
+                name,
+                expr,
+                target,
+                forall,
+                tags,
+                redefine,
+            } => {
+                pad(sink, indent * 2)?;
+                write_tags(sink, tags)?;
+                if *redefine {
+                    write!(sink, "redefine ")?
+                }
+                if target.is_some() {
+                    write!(sink, "lemma ")?
+                }
+                write!(sink, "{} :: ", name)?;
+                if !forall.is_empty() {
+                    write!(sink, "forall {}. ", forall.join(", "))?
+                }
+                match target {
+                    Some(target) => writeln!(sink, "{} == {} {{", expr, target)?,
+                    None => writeln!(sink, "{} {{", expr)?,
+                }
+                indent += 1
+            }
+            Command::StartShaping(_, expr) => {
+                pad(sink, indent * 2)?;
+                writeln!(sink, "{} {{", expr)?;
+                indent += 1
+            }
+            Command::ApplyRule {
+                strategy_name,
+                traversal,
+                applied_rule,
+                side,
+                ..
+            } => {
+                pad(sink, indent * 2)?;
+                if let Some(side) = side {
+                    write!(sink, "{}: ", side.name())?
+                }
+                match applied_rule {
+                    AppliedRule::ByName {
+                        name,
+                        args,
+                        reversed,
+                        bindings,
+                        ..
+                    } => {
+                        write_applied_rule_name(sink, name, args)?;
+                        write_bindings(sink, bindings)?;
+                        if *reversed {
+                            write!(sink, " |! {}", strategy_name)?
+                        } else {
+                            write!(sink, " | {}", strategy_name)?
+                        }
+                    }
+                    AppliedRule::Anonymous { head, body, .. } => {
+                        write!(sink, "{} = {} | {}", head, body, strategy_name)?
+                    }
+                }
+                for modifier in traversal.modifier_names() {
+                    write!(sink, " {}", modifier)?
+                }
+                writeln!(sink)?
+            }
+            Command::Matches {
+                traversal,
+                applied_rule,
+                ..
+            } => {
+                pad(sink, indent * 2)?;
+                match applied_rule {
+                    AppliedRule::ByName {
+                        name,
+                        args,
+                        bindings,
+                        ..
+                    } => {
+                        write_applied_rule_name(sink, name, args)?;
+                        write_bindings(sink, bindings)?;
+                        write!(sink, " ?")?
+                    }
+                    AppliedRule::Anonymous { head, body, .. } => {
+                        write!(sink, "{} = {} ?", head, body)?
+                    }
+                }
+                for modifier in traversal.modifier_names() {
+                    write!(sink, " {}", modifier)?
+                }
+                writeln!(sink)?
+            }
+            Command::Focus(_, path) => {
+                pad(sink, indent * 2)?;
+                writeln!(sink, "focus {} {{", format_path(path))?;
+                indent += 1
+            }
+            Command::FinishShaping(_, target) => {
+                // The shaping may have been opened by a loaded file
+                indent = indent.saturating_sub(1);
+                pad(sink, indent * 2)?;
+                match target {
+                    Some(target) => writeln!(sink, "}} == {}", target)?,
+                    None => writeln!(sink, "}}")?,
+                }
+            }
+            Command::Simp(_) => {
+                pad(sink, indent * 2)?;
+                writeln!(sink, "simp")?
+            }
+            Command::Prove {
+                start,
+                goal,
+                rule_names,
+                depth,
+                ..
+            } => {
+                pad(sink, indent * 2)?;
+                write!(sink, "prove {} == {}", start, goal)?;
+                if let Some(rule_names) = rule_names {
+                    write!(sink, " using {}", rule_names.join(", "))?
+                }
+                writeln!(sink, " depth {}", depth)?
+            }
+            Command::Limit(_, limit, value) => {
+                pad(sink, indent * 2)?;
+                writeln!(sink, "limit {} {}", limit.name(), value)?
+            }
+            Command::Unbound(_, unbound) => {
+                pad(sink, indent * 2)?;
+                writeln!(sink, "unbound {}", unbound.name())?
+            }
+            Command::Cases {
+                subject, patterns, ..
+            } => {
+                pad(sink, indent * 2)?;
+                let patterns: Vec<String> = patterns.iter().map(|p| p.to_string()).collect();
+                writeln!(sink, "cases {} of {}", subject, patterns.join(" | "))?
+            }
+            Command::Induction { subject, data, .. } => {
+                pad(sink, indent * 2)?;
+                writeln!(sink, "induction {} on {}", subject, data)?
+            }
+            Command::Generalize(_, first, second) => {
+                pad(sink, indent * 2)?;
+                writeln!(sink, "generalize {}, {}", first, second)?
+            }
+            Command::Data {
+                name, constructors, ..
+            } => {
+                pad(sink, indent * 2)?;
+                let constructors: Vec<String> =
+                    constructors.iter().map(|c| c.to_string()).collect();
+                writeln!(sink, "data {} = {}", name, constructors.join(" | "))?
+            }
+            Command::Theory(_, ops) => {
+                pad(sink, indent * 2)?;
+                let ops: Vec<String> = ops.iter().map(|op| op.to_string()).collect();
+                writeln!(sink, "theory ac {}", ops.join(", "))?
+            }
+            Command::UndoRule(_, count) => {
+                pad(sink, indent * 2)?;
+                match count {
+                    1 => writeln!(sink, "undo")?,
+                    _ => writeln!(sink, "undo {}", count)?,
+                }
+            }
+            Command::Redo(_, count) => {
+                pad(sink, indent * 2)?;
+                match count {
+                    1 => writeln!(sink, "redo")?,
+                    _ => writeln!(sink, "redo {}", count)?,
+                }
+            }
+            Command::Quit => {
+                pad(sink, indent * 2)?;
+                writeln!(sink, "quit")?
+            }
+            Command::ListRules(_, pattern) => {
+                pad(sink, indent * 2)?;
+                match pattern {
+                    Some(pattern) => writeln!(sink, "rules matching {}", pattern)?,
+                    None => writeln!(sink, "rules")?,
+                }
+            }
+            Command::ShowRule(_, name) => {
+                pad(sink, indent * 2)?;
+                writeln!(sink, "show {}", name)?
+            }
+            Command::DeleteRule(_, name) => {
+                pad(sink, indent * 2)?;
+                writeln!(sink, "delete {}", name)?
+            }
+            Command::Load(_, name, module) => {
+                pad(sink, indent * 2)?;
+                match module {
+                    Some(module) => writeln!(sink, "load \"{}\" as {}", name, module)?,
+                    None => writeln!(sink, "load \"{}\"", name)?,
+                }
+            }
+            Command::Open(_, module) => {
+                pad(sink, indent * 2)?;
+                writeln!(sink, "open {}", module)?
+            }
+            Command::Save(_, name) => {
+                pad(sink, indent * 2)?;
+                writeln!(sink, "save \"{}\"", name)?
+            }
+        }
+    }
+    Ok(())
+}
+
+impl Context {
+    fn new() -> Self {
+        let mut rules = HashMap::new();
+        rules.insert("replace".to_string(), Rule::Replace);
+        Self {
+            rules,
+            data_types: HashMap::new(),
+            rule_tags: HashMap::new(),
+            rule_origins: HashMap::new(),
+            shaping_stack: Default::default(),
+            finished_frames: Default::default(),
+            quit: false,
+            history: Default::default(),
+            undone: Default::default(),
+            limits: Limits::default(),
+            unbound: Unbound::Error,
+            ac_ops: BTreeSet::new(),
+            main_file: None,
+            loading: Vec::new(),
+            restoring: 0,
+            loaded: HashSet::new(),
+            module: None,
+            modules: HashSet::new(),
+            opened: Vec::new(),
+        }
+    }
+
+    /// Name of the rule defined in the current module
+    fn qualified_name(&self, name: &str) -> String {
+        match &self.module {
+            Some(module) => format!("{}.{}", module, name),
+            None => name.to_string(),
+        }
+    }
+
+    /// Finds the full name of the rule referred to by `name`. The rules
+    /// of the module that is being loaded come first, then the rule
+    /// with exactly that name and the rules of the opened modules.
+    fn resolve_rule_name(&self, name: &str, loc: &Loc) -> Result<String, RuntimeError> {
+        if self.module.is_some() {
+            let local = self.qualified_name(name);
+            if self.rules.contains_key(&local) {
+                return Ok(local);
+            }
+        }
+        let mut candidates: Vec<String> = std::iter::once(name.to_string())
+            .chain(
+                self.opened
+                    .iter()
+                    .map(|module| format!("{}.{}", module, name)),
+            )
+            .filter(|candidate| self.rules.contains_key(candidate))
+            .collect();
+        candidates.sort();
+        candidates.dedup();
+        match candidates.len() {
+            0 => Err(RuntimeError::RuleDoesNotExist(
+                name.to_string(),
+                loc.clone(),
+            )),
+            1 => Ok(candidates.remove(0)),
+            _ => Err(RuntimeError::AmbiguousRule(
+                name.to_string(),
+                candidates,
+                loc.clone(),
+            )),
+        }
+    }
+
+    /// Finds the file requested by a `load` located at `loc`. Relative
+    /// paths are looked up next to the loading file (or in the current
+    /// directory for the REPL) and then in the directories listed in the
+    /// `NOQ_PATH` environment variable.
+    fn resolve_load_path(loc: &Loc, file_path: &str) -> PathBuf {
+        let path = Path::new(file_path);
+        if path.is_absolute() {
+            return path.to_path_buf();
+        }
+        let base = match &loc.file_path {
+            Some(loading_file) => Path::new(loading_file)
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .to_path_buf(),
+            None => PathBuf::new(),
+        };
+        let local = base.join(path);
+        if local.exists() {
+            return local;
+        }
+        if let Some(search_path) = env::var_os("NOQ_PATH") {
+            for dir in env::split_paths(&search_path) {
+                let candidate = dir.join(path);
+                if candidate.exists() {
+                    return candidate;
+                }
+            }
+        }
+        local
+    }
+
+    /// Whether the processed commands are put into the history
+    fn records_history(&self) -> bool {
+        self.loading.len() == self.restoring
+    }
+
+    /// Interprets the file unless it has been loaded already. Returns
+    /// whether the `load` itself belongs to the history. It doesn't if
+    /// the file has been loaded already or if it's a saved session, i.e.
+    /// it leaves some shaping unfinished. The commands of a session are
+    /// put into the history instead, and the session can be loaded again.
+    fn load_file(
+        &mut self,
+        loc: &Loc,
+        file_path: &str,
+        module: Option<&str>,
+    ) -> Result<bool, Error> {
+        let path = Self::resolve_load_path(loc, file_path);
+        let source = match fs::read_to_string(&path) {
+            Ok(source) => source,
+            Err(err) => return Err(RuntimeError::CouldNotLoadFile(loc.clone(), err).into()),
+        };
+        let canonical = fs::canonicalize(&path).unwrap_or_else(|_| path.clone());
+        if self.main_file.as_ref() == Some(&canonical) || self.loading.contains(&canonical) {
+            let mut chain: Vec<PathBuf> = self.main_file.iter().cloned().collect();
+            chain.extend(self.loading.iter().cloned());
+            chain.push(canonical.clone());
+            let start = chain
+                .iter()
+                .position(|file| *file == canonical)
+                .expect("The file was found in the chain above");
+            return Err(RuntimeError::LoadCycle(chain.split_off(start), loc.clone()).into());
+        }
+        let module = match module {
+            Some(module) => Some(self.qualified_name(module)),
+            None => self.module.clone(),
+        };
+        let mut lexer = Lexer::new(source.chars(), Some(path.display().to_string()));
+        let mut commands = Vec::new();
+        let mut syntax_error = None;
+        while lexer.peek_token().kind != TokenKind::End {
+            match Command::parse(&mut lexer) {
+                Ok(command) => commands.push(command),
+                Err(err) => {
+                    syntax_error = Some(err);
+                    break;
+                }
+            }
+        }
+        let open_shapings = commands.iter().fold(0usize, |open, command| match command {
+            Command::DefineRuleViaShaping { .. }
+            | Command::StartShaping(..)
+            | Command::Focus(..) => open + 1,
+            Command::FinishShaping(..) => open.saturating_sub(1),
+            _ => open,
+        });
+        let session = open_shapings > 0;
+        if !session && !self.loaded.insert((canonical.clone(), module.clone())) {
+            return Ok(false);
+        }
+        if let Some(module) = &module {
+            self.modules.insert(module.clone());
+        }
+        let restoring = session && self.records_history();
+        let outer_module = std::mem::replace(&mut self.module, module);
+        self.loading.push(canonical);
+        if restoring {
+            self.restoring += 1;
+        }
+        let mut result = Ok(());
+        for command in commands {
+            result = self.process_command(command);
+            if result.is_err() {
+                break;
+            }
+        }
+        if restoring {
+            self.restoring -= 1;
+        }
+        self.loading.pop();
+        self.module = outer_module;
+        result?;
+        match syntax_error {
+            Some(err) => Err(err.into()),
+            None => Ok(!restoring),
+        }
+    }
+
+    /// Rewrites `expr` to the normal form using all the rules tagged
+    /// with `@simp`. Returns the normal form and the names of the used
+    /// rules in the order of their first use.
+    ///
+    /// Fails if the process goes beyond the [`Limits`].
+    ///
+    /// The rules are treated as equations that can be used in both
+    /// directions, but a rewrite is only done if it makes the expression
+    /// smaller according to [`Expr::term_cmp`]. That is what guarantees
+    /// the termination of the process.
+    fn simplify(&self, expr: &Expr, loc: &Loc) -> Result<(Expr, Vec<String>), RuntimeError> {
+        fn simplify_step<'a>(
+            expr: &Expr,
+            equations: &[(&'a str, &Expr, &Expr)],
+        ) -> Option<(Expr, &'a str)> {
+            let subexprs = expr.subexprs();
+            for (index, subexpr) in subexprs.iter().enumerate() {
+                if let Some((new_subexpr, name)) = simplify_step(subexpr, equations) {
+                    let mut new_subexprs: Vec<Expr> = subexprs.iter().map(|&x| x.clone()).collect();
+                    new_subexprs[index] = new_subexpr;
+                    return Some((expr.with_subexprs(new_subexprs), name));
+                }
+            }
+            for (name, lhs, rhs) in equations {
+                if let Some(bindings) = lhs.pattern_match(expr) {
+                    let new_expr = rhs.substitute(&bindings);
+                    if new_expr.term_cmp(expr).is_lt() {
+                        return Some((new_expr, name));
+                    }
+                }
+            }
+            None
+        }
+
+        let mut names: Vec<&String> = self
+            .rule_tags
+            .iter()
+            .filter(|(_, tags)| tags.iter().any(|tag| tag == "simp"))
+            .map(|(name, _)| name)
+            .collect();
+        names.sort();
+
+        let mut equations = Vec::new();
+        for name in names {
+            let rule = &self.rules[name];
+            // The parameterized rules can't be applied without the arguments
+            if !rule.params().is_empty() {
+                continue;
+            }
+            for clause in rule.clauses() {
+                if let Rule::User { head, body, .. } = clause {
+                    let head_vars = head.vars();
+                    let body_vars = body.vars();
+                    // A direction that would leave some of the variables unbound is not usable
+                    if body_vars.is_subset(&head_vars) {
+                        equations.push((name.as_str(), head, body));
+                    }
+                    if head_vars.is_subset(&body_vars) {
+                        equations.push((name.as_str(), body, head));
+                    }
+                }
+            }
+        }
+
+        let mut expr = expr.clone();
+        let mut used_rules = Vec::<String>::new();
+        let mut steps = 0;
+        while let Some((new_expr, name)) = simplify_step(&expr, &equations) {
+            steps += 1;
+            self.limits.check_steps(steps, loc)?;
+            if !used_rules.iter().any(|used| used == name) {
+                used_rules.push(name.to_string());
+            }
+            expr = new_expr;
+        }
+        Ok((expr, used_rules))
+    }
+
+    /// Searches breadth first for the shortest sequence of at most
+    /// `depth` rule applications that turns `start` into `goal`. Every
+    /// rule is tried at every position it matches, the reversible rules
+    /// are also tried reversed. Returns the commands of the found
+    /// shaping.
+    ///
+    /// The search stops at the first error other than an `apply_rule`
+    /// call that can't be carried out.
+    ///
+    /// If the goal is not reachable or the search goes beyond the
+    /// [`Limits`], the expressions closest to the goal are reported.
+    fn prove(
+        &self,
+        loc: &Loc,
+        start: &Expr,
+        goal: &Expr,
+        rule_names: &[String],
+        depth: usize,
+    ) -> Result<Vec<Command>, RuntimeError> {
+        let rule_names = rule_names
+            .iter()
+            .map(|name| self.resolve_rule_name(name, loc))
+            .collect::<Result<Vec<String>, RuntimeError>>()?;
+        let mut rules = Vec::new();
+        for name in &rule_names {
+            let rule = &self.rules[name];
+            let params = rule.params();
+            if !params.is_empty() {
+                return Err(RuntimeError::RuleArity {
+                    name: name.clone(),
+                    expected: params.len(),
+                    actual: 0,
+                    loc: loc.clone(),
+                });
+            }
+            // The shaping must replay the same way, so the rules that leave
+            // variables of their bodies unbound are not tried
+            if rule.unbound_vars().is_empty() {
+                rules.push((name, false, rule.clone()));
+            }
+            if !matches!(rule, Rule::Replace) {
+                let reversed = rule.reversed(loc)?;
+                if reversed.unbound_vars().is_empty() {
+                    rules.push((name, true, reversed));
+                }
+            }
+        }
+
+        // Every reached expression with the amount of steps it took and
+        // the previous expression along with the step that produced it
+        type Step<'a> = (Expr, &'a String, bool, usize);
+        let mut reached: HashMap<Expr, (usize, Option<Step>)> = HashMap::new();
+        let shaping_to = |reached: &HashMap<Expr, (usize, Option<Step>)>, expr: &Expr| {
+            let mut commands = vec![Command::FinishShaping(loc.clone(), Some(goal.clone()))];
+            let mut current = expr;
+            while let Some((_, Some((previous, name, reversed, index)))) = reached.get(current) {
+                commands.push(Command::ApplyRule {
+                    loc: loc.clone(),
+                    strategy_name: index.to_string(),
+                    traversal: Traversal::default(),
+                    applied_rule: AppliedRule::ByName {
+                        loc: loc.clone(),
+                        name: name.to_string(),
+                        args: vec![],
+                        reversed: *reversed,
+                        bindings: vec![],
+                    },
+                    side: None,
+                });
+                current = previous;
+            }
+            commands.push(Command::StartShaping(loc.clone(), start.clone()));
+            commands.reverse();
+            commands
+        };
+
+        reached.insert(start.clone(), (0, None));
+        if start == goal {
+            return Ok(shaping_to(&reached, start));
+        }
+        let mut failure = RuntimeError::ProofNotFound(depth, loc.clone());
+        let mut queue = VecDeque::from([(start.clone(), 0)]);
+        'search: while let Some((expr, steps)) = queue.pop_front() {
+            if steps >= depth {
+                continue;
+            }
+            for (name, reversed, rule) in rules.iter() {
+                let rewrites = match rule.rewrites(&expr, self, loc) {
+                    Ok(rewrites) => rewrites,
+                    Err(err) => {
+                        failure = err;
+                        break 'search;
+                    }
+                };
+                for (index, new_expr) in rewrites {
+                    if !reached.contains_key(&new_expr) {
+                        let checked = self
+                            .limits
+                            .check_steps(reached.len(), loc)
+                            .and_then(|_| self.limits.check_expr(&new_expr, loc));
+                        if let Err(err) = checked {
+                            failure = err;
+                            break 'search;
+                        }
+                        let step = (expr.clone(), *name, *reversed, index);
+                        reached.insert(new_expr.clone(), (steps + 1, Some(step)));
+                        if new_expr == *goal {
+                            return Ok(shaping_to(&reached, goal));
+                        }
+                        queue.push_back((new_expr, steps + 1));
+                    }
+                }
+            }
+        }
+
+        const CLOSEST_COUNT: usize = 3;
+        let mut closest: Vec<(usize, &Expr)> = reached
+            .keys()
+            .map(|expr| (expr.distance(goal), expr))
+            .collect();
+        closest.sort_by(|(d1, e1), (d2, e2)| d1.cmp(d2).then_with(|| e1.term_cmp(e2)));
+        println!("closest expressions reached:");
+        for (distance, expr) in closest.iter().take(CLOSEST_COUNT) {
+            println!(
+                "  {} (distance {}, {} steps)",
+                expr, distance, reached[*expr].0
+            );
+        }
+        Err(failure)
+    }
+
+    fn resolve_applied_rule(&self, applied_rule: AppliedRule) -> Result<Rule, RuntimeError> {
+        match applied_rule {
+            AppliedRule::ByName {
+                loc,
+                name,
+                args,
+                reversed,
+                mut bindings,
+            } => {
+                let name = self.resolve_rule_name(&name, &loc)?;
+                let rule = &self.rules[&name];
+                let params = rule.params();
+                let all_bound = params
+                    .iter()
+                    .all(|param| bindings.iter().any(|(bound, _)| bound == param));
+                if args.len() != params.len() && !(args.is_empty() && all_bound) {
+                    return Err(RuntimeError::RuleArity {
+                        name,
+                        expected: params.len(),
+                        actual: args.len(),
+                        loc,
+                    });
+                }
+                bindings.extend(params.iter().cloned().zip(args));
+                let rule = if reversed {
+                    rule.reversed(&loc)?
+                } else {
+                    rule.clone()
+                };
+                rule.bind(&bindings, &loc)
+            }
+            AppliedRule::Anonymous { loc, head, body } => Ok(Rule::User {
+                loc,
+                head,
+                body,
+                params: vec![],
+            }),
+        }
+    }
+
+    /// Builds the rule out of the existing rules. The sequence of two
+    /// rules is the first rule with its result unified with the head of
+    /// the second rule.
+    fn derive_rule(&self, derivation: &Derivation, loc: &Loc) -> Result<Rule, RuntimeError> {
+        match derivation {
+            Derivation::Rule { name, args } => {
+                let rule = self.resolve_applied_rule(AppliedRule::ByName {
+                    loc: loc.clone(),
+                    name: name.clone(),
+                    args: args.clone(),
+                    reversed: false,
+                    bindings: vec![],
+                })?;
+                match rule {
+                    Rule::User { .. } | Rule::Clauses(_) => Ok(rule.defined_at(loc)),
+                    Rule::Replace => Err(RuntimeError::BuiltinRule(name.clone(), loc.clone())),
+                }
+            }
+            Derivation::Sym(derivation) => self.derive_rule(derivation, loc)?.reversed(loc),
+            Derivation::Seq(first, second) => {
+                let first = self.derive_rule(first, loc)?;
+                let second = self.derive_rule(second, loc)?;
+                // Every clause of the first rule is composed with every
+                // clause of the second one that it fits
+                let mut clauses = vec![];
+                let mut mismatch = None;
+                for first in first.clauses() {
+                    for second in second.clauses() {
+                        match compose_clauses(first, second, loc) {
+                            Ok(clause) => clauses.push(clause),
+                            Err(err) => {
+                                mismatch.get_or_insert(err);
+                            }
+                        }
+                    }
+                }
+                match mismatch {
+                    Some(err) if clauses.is_empty() => Err(err),
+                    _ => Ok(Rule::from_clauses(clauses)),
+                }
+            }
+        }
+    }
+
+    /// Deals with the variables of the rule body that the match gives no
+    /// values to, according to the `unbound` setting. The fresh symbols
+    /// are picked so they don't clash with the symbols of the expression
+    /// the rule is applied to.
+    fn handle_unbound(&self, rule: Rule, expr: &Expr, loc: &Loc) -> Result<Rule, RuntimeError> {
+        let unbound = rule.unbound_vars();
+        if unbound.is_empty() {
+            return Ok(rule);
+        }
+        match self.unbound {
+            Unbound::Error => Err(RuntimeError::UnboundVariables(unbound, loc.clone())),
+            Unbound::Warn => {
+                eprintln!(
+                    "WARNING: the rule body leaves variables unbound: {}",
+                    unbound.join(", ")
+                );
+                Ok(rule)
+            }
+            Unbound::Fresh => {
+                let mut taken = expr.syms();
+                for clause in rule.clauses() {
+                    if let Rule::User { head, body, .. } = clause {
+                        taken.extend(head.syms());
+                        taken.extend(body.syms());
+                    }
+                }
+                let mut bindings = HashMap::new();
+                for name in unbound {
+                    let fresh = (1..)
+                        .map(|index| format!("{}_{}", name.to_lowercase(), index))
+                        .find(|fresh| !taken.contains(fresh))
+                        .expect("There are infinitely many candidates");
+                    println!("introduced fresh symbol `{}` for {}", fresh, name);
+                    taken.insert(fresh.clone());
+                    bindings.insert(name, Expr::Sym(fresh));
+                }
+                let clauses = rule
+                    .clauses()
+                    .iter()
+                    .map(|clause| match clause {
+                        Rule::User {
+                            loc,
+                            head,
+                            body,
+                            params,
+                        } => Rule::User {
+                            loc: loc.clone(),
+                            head: head.clone(),
+                            body: body.substitute(&bindings),
+                            params: params.clone(),
+                        },
+                        _ => unreachable!("The clauses are always user rules"),
+                    })
+                    .collect();
+                Ok(Rule::from_clauses(clauses))
+            }
+        }
+    }
+
+    /// Checks the variables of the body of the defined rule according
+    /// to the `unbound` setting. With the fresh symbols the check happens
+    /// when the rule is applied.
+    fn check_defined_rule(&self, rule: &Rule, loc: &Loc) -> Result<(), RuntimeError> {
+        let unbound = rule.unbound_vars();
+        if unbound.is_empty() {
+            return Ok(());
+        }
+        match self.unbound {
+            Unbound::Error => Err(RuntimeError::UnboundInDefinition(unbound, loc.clone())),
+            Unbound::Warn => {
+                eprintln!(
+                    "{}: WARNING: the rule body uses variables that don't appear in its head: {}",
+                    loc,
+                    unbound.join(", ")
+                );
+                Ok(())
+            }
+            Unbound::Fresh => Ok(()),
+        }
+    }
+
+    /// Turns the rule argument of the meta rule `apply_rule` into the
+    /// rule: either a name of an existing rule or `sym(<rule>)` for the
+    /// reversed rule
+    fn rule_from_expr(&self, expr: &Expr, loc: &Loc) -> Result<Rule, RuntimeError> {
+        match expr {
+            Expr::Sym(name) => self.resolve_applied_rule(AppliedRule::ByName {
+                loc: loc.clone(),
+                name: name.clone(),
+                args: vec![],
+                reversed: false,
+                bindings: vec![],
+            }),
+            Expr::Fun(head, args) if **head == expr!(sym) && args.len() == 1 => {
+                self.rule_from_expr(&args[0], loc)?.reversed(loc)
+            }
+            Expr::Fun(head, args) if matches!(**head, Expr::Sym(_)) => {
+                self.resolve_applied_rule(AppliedRule::ByName {
+                    loc: loc.clone(),
+                    name: head.to_string(),
+                    args: args.clone(),
+                    reversed: false,
+                    bindings: vec![],
+                })
+            }
+            _ => Err(RuntimeError::NotARule(expr.clone(), loc.clone())),
+        }
+    }
+
+    /// Checks that a rule with the given name can be defined. An existing
+    /// rule can only be replaced by the explicit redefinition.
+    fn check_rule_definition(
+        &self,
+        name: &str,
+        loc: &Loc,
+        redefine: bool,
+    ) -> Result<(), RuntimeError> {
+        // The hypotheses of the goals come and go with the active goal,
+        // so their names can't be given to other rules
+        let reserved = self.shaping_stack.iter().any(|frame| {
+            let pending = frame.goals.pending.iter().flat_map(|goal| &goal.hypotheses);
+            frame
+                .goals
+                .hypotheses
+                .iter()
+                .chain(pending)
+                .any(|(hypothesis, _)| hypothesis == name)
+        });
+        if reserved {
+            return Err(RuntimeError::HypothesisName(name.to_string(), loc.clone()));
+        }
+        match self.rules.get(name) {
+            Some(Rule::Replace) => Err(RuntimeError::BuiltinRule(name.to_string(), loc.clone())),
+            Some(rule) if !redefine => Err(RuntimeError::RuleAlreadyExists(
+                name.to_string(),
+                loc.clone(),
+                rule.loc().cloned(),
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Removes the rule along with its tags and shaping
+    fn take_rule(&mut self, name: &str) -> Option<TakenRule> {
+        let rule = self.rules.remove(name)?;
+        Some((
+            rule,
+            self.rule_tags.remove(name),
+            self.rule_origins.remove(name),
+        ))
+    }
+
+    /// Warns about the rules built with the rule that is gone
+    fn warn_about_dependents(&self, name: &str, what_happened: &str) {
+        let mut dependents: Vec<String> = self
+            .rule_origins
+            .iter()
+            .filter(|(dependent, origin)| {
+                // The rules of a module refer to each other without the module name
+                let module = dependent.rsplit_once('.').map(|(module, _)| module);
+                let refers = |used: &str| {
+                    used == name
+                        || module.is_some_and(|module| *name == format!("{}.{}", module, used))
+                };
+                origin.iter().any(|command| match command {
+                    Command::ApplyRule {
+                        applied_rule: AppliedRule::ByName { name: used, .. },
+                        ..
+                    } => refers(used),
+                    Command::DefineDerivedRule { derivation, .. } => {
+                        derivation.rule_names().into_iter().any(refers)
+                    }
+                    _ => false,
+                })
+            })
+            .map(|(dependent, _)| format!("`{}`", dependent))
+            .collect();
+        if !dependents.is_empty() {
+            dependents.sort();
+            eprintln!(
+                "WARNING: rule `{}` was {}, but {} {} built with it",
+                name,
+                what_happened,
+                dependents.join(", "),
+                if dependents.len() == 1 { "was" } else { "were" }
+            );
+        }
+    }
+
+    /// Replaces the expression of the current shaping frame with the
+    /// result of the command that is about to be put into the history
+    fn push_shaping_step(&mut self, new_expr: Expr, command: &Command) {
+        let goals = self
+            .shaping_stack
+            .last()
+            .expect("The shaping frame must be checked before the step is made")
+            .goals
+            .clone();
+        self.push_shaping_step_with_goals(new_expr, goals, command)
+    }
+
+    /// Like [`Context::push_shaping_step`], but also replaces the goals
+    /// of the frame. The closed goal gives way to the next pending one.
+    fn push_shaping_step_with_goals(&mut self, new_expr: Expr, goals: Goals, command: &Command) {
+        let index = if self.records_history() {
+            Some(self.history.len())
+        } else {
+            None
+        };
+        let (new_expr, goals) = self.advance_goals(new_expr, goals);
+        let frame = self
+            .shaping_stack
+            .last_mut()
+            .expect("The shaping frame must be checked before the step is made");
+        let previous_hypotheses = frame.goals.hypotheses.clone();
+        self.switch_hypotheses(&previous_hypotheses, &goals.hypotheses);
+        let frame = self
+            .shaping_stack
+            .last_mut()
+            .expect("The shaping frame must be checked before the step is made");
+        let previous_expr = std::mem::replace(&mut frame.expr, new_expr);
+        let previous_goals = std::mem::replace(&mut frame.goals, goals);
+        frame.history.push(ShapingStep {
+            previous_expr,
+            previous_goals,
+            command: command.clone(),
+            index,
+        });
+    }
+
+    /// Moves on to the next pending goal for as long as the active one
+    /// is closed
+    fn advance_goals(&self, mut expr: Expr, mut goals: Goals) -> (Expr, Goals) {
+        self.report_closed_equation(&expr);
+        while self.equation_closed(&expr) == Some(true) && !goals.pending.is_empty() {
+            let goal = goals.pending.remove(0);
+            println!("case {}:", goal.case);
+            println!(" => {}", goal.expr);
+            self.report_closed_equation(&goal.expr);
+            expr = goal.expr;
+            goals.active = Some(goal.case);
+            goals.hypotheses = goal.hypotheses;
+        }
+        (expr, goals)
+    }
+
+    /// Replaces the hypotheses of the previously active goal with the
+    /// ones of the newly active goal in the rules of the context
+    fn switch_hypotheses(&mut self, from: &[(String, Rule)], to: &[(String, Rule)]) {
+        for (name, _) in from {
+            self.rules.remove(name);
+        }
+        for (name, rule) in to {
+            self.rules.insert(name.clone(), rule.clone());
+        }
+    }
+
+    /// The shaped equation that is about to be split into cases of the
+    /// subject
+    fn goal_to_split(&self, subject: &Expr, loc: &Loc) -> Result<Expr, RuntimeError> {
+        let frame = match self.shaping_stack.last() {
+            Some(frame) => frame,
+            None => return Err(RuntimeError::NoShapingInPlace(loc.clone())),
+        };
+        let goal = &frame.expr;
+        if !matches!(goal, Expr::Op(Op::Eql, ..)) {
+            return Err(RuntimeError::CasesNotEquation(goal.clone(), loc.clone()));
+        }
+        if !goal.contains(subject) {
+            return Err(RuntimeError::CaseNotInGoal(
+                subject.clone(),
+                goal.clone(),
+                loc.clone(),
+            ));
+        }
+        Ok(goal.clone())
+    }
+
+    /// Replaces the active goal with a goal per case of the subject. The
+    /// goals of the cases come before the goals that were already pending.
+    fn split_goal(&mut self, subject: &Expr, cases: Vec<Case>, command: &Command) {
+        let frame = self
+            .shaping_stack
+            .last()
+            .expect("The shaping frame must be checked before the goal is split");
+        let mut goals = cases
+            .into_iter()
+            .map(|(pattern, hypotheses)| {
+                let case = format!("{} := {}", subject, pattern);
+                let mut all_hypotheses = frame.goals.hypotheses.clone();
+                all_hypotheses.extend(hypotheses);
+                Goal {
+                    case: match &frame.goals.active {
+                        Some(active) => format!("{}, {}", active, case),
+                        None => case,
+                    },
+                    expr: frame.expr.replace(subject, &pattern),
+                    hypotheses: all_hypotheses,
+                }
+            })
+            .collect::<Vec<Goal>>()
+            .into_iter();
+        let first = goals.next().expect("There is at least one case");
+        let mut pending: Vec<Goal> = goals.collect();
+        pending.extend(frame.goals.pending.iter().cloned());
+        println!("case {}:", first.case);
+        println!(" => {}", first.expr);
+        let goals = Goals {
+            active: Some(first.case),
+            hypotheses: first.hypotheses,
+            pending,
+        };
+        self.push_shaping_step_with_goals(first.expr, goals, command);
+    }
+
+    /// The cases of the induction over the data type. The arguments of
+    /// the constructors become fresh symbols, and the recursive ones
+    /// give the induction hypotheses.
+    fn induction_cases(
+        &self,
+        goal: &Expr,
+        subject: &Expr,
+        data: &str,
+        loc: &Loc,
+    ) -> Result<Vec<Case>, RuntimeError> {
+        let constructors = match self.data_types.get(data) {
+            Some(constructors) => constructors,
+            None => return Err(RuntimeError::UnknownData(data.to_string(), loc.clone())),
+        };
+        let (lhs, rhs) = match goal {
+            Expr::Op(Op::Eql, lhs, rhs) => (lhs, rhs),
+            _ => unreachable!("The goal was checked to be an equation"),
+        };
+        let base = match subject {
+            Expr::Sym(name) | Expr::Var(name) => name.to_lowercase(),
+            _ => "x".to_string(),
+        };
+        let mut taken_syms = goal.syms();
+        let mut taken_names: BTreeSet<String> = self.rules.keys().cloned().collect();
+        let mut cases = Vec::new();
+        for constructor in constructors {
+            let (head, arg_types) = match constructor {
+                Expr::Fun(head, arg_types) => (head, &arg_types[..]),
+                _ => (&Box::new(constructor.clone()), &[][..]),
+            };
+            let mut args = Vec::new();
+            let mut hypotheses = Vec::new();
+            for arg_type in arg_types {
+                let fresh = (1..)
+                    .map(|index| format!("{}_{}", base, index))
+                    .find(|fresh| !taken_syms.contains(fresh))
+                    .expect("There are infinitely many candidates");
+                taken_syms.insert(fresh.clone());
+                let arg = Expr::Sym(fresh);
+                if *arg_type == Expr::Sym(data.to_string()) {
+                    let name = std::iter::once("ih".to_string())
+                        .chain((1..).map(|index| format!("ih_{}", index)))
+                        .find(|name| !taken_names.contains(name))
+                        .expect("There are infinitely many candidates");
+                    taken_names.insert(name.clone());
+                    let hypothesis = Rule::User {
+                        loc: loc.clone(),
+                        head: lhs.replace(subject, &arg),
+                        body: rhs.replace(subject, &arg),
+                        params: vec![],
+                    };
+                    hypotheses.push((name, hypothesis));
+                }
+                args.push(arg);
+            }
+            let pattern = if args.is_empty() {
+                *head.clone()
+            } else {
+                Expr::Fun(head.clone(), args)
+            };
+            cases.push((pattern, hypotheses));
+        }
+        Ok(cases)
+    }
 
-struct ShapingFrame {
-    expr: Expr,
-    history: Vec<Expr>,
-    rule_via_shaping: Option<(String, Expr)>,
-}
+    /// The head of the constructor or of the case made of it along with
+    /// the amount of arguments. Variables and operators have none.
+    fn constructor_head(expr: &Expr) -> Option<(&Expr, usize)> {
+        match expr {
+            Expr::Sym(_) => Some((expr, 0)),
+            Expr::Fun(head, args) => Some((head, args.len())),
+            _ => None,
+        }
+    }
 
-impl ShapingFrame {
-    fn new(expr: Expr) -> Self {
-        Self {
-            expr,
-            history: Vec::new(),
-            rule_via_shaping: None,
+    /// Checks that the cases cover every value of the data type their
+    /// constructors come from
+    fn check_cases_exhaustive(&self, patterns: &[Expr], loc: &Loc) -> Result<(), RuntimeError> {
+        let mut data_names: Vec<&String> = self.data_types.keys().collect();
+        data_names.sort();
+        let data = match patterns.iter().find_map(Self::constructor_head) {
+            Some(head) => data_names
+                .into_iter()
+                .find(|name| {
+                    self.data_types[*name]
+                        .iter()
+                        .any(|constructor| Self::constructor_head(constructor) == Some(head))
+                })
+                .cloned()
+                .ok_or_else(|| {
+                    let case = patterns
+                        .iter()
+                        .find(|pattern| Self::constructor_head(pattern) == Some(head))
+                        .expect("The head was found among the cases");
+                    RuntimeError::UnknownConstructor(case.clone(), loc.clone())
+                })?,
+            // Nothing but variables and operators, so only a variable
+            // covers everything
+            None => String::new(),
+        };
+        let rows = patterns
+            .iter()
+            .map(|pattern| vec![pattern.clone()])
+            .collect();
+        match self.missing_case(rows, &[Expr::Sym(data)]) {
+            Some(mut missing) => Err(RuntimeError::CasesNotExhaustive(
+                missing.remove(0),
+                loc.clone(),
+            )),
+            None => Ok(()),
         }
     }
 
-    fn new_rule_via_shaping(name: String, head: Expr) -> Self {
-        Self {
-            expr: head.clone(),
-            history: Vec::new(),
-            rule_via_shaping: Some((name, head)),
+    /// Finds the values of the given types that none of the rows of the
+    /// patterns match. The values are patterns themselves, with `_` for
+    /// any value. Only the constructors of the declared data types are
+    /// taken apart, the values of the other types are covered by
+    /// variables only.
+    fn missing_case(&self, rows: Vec<Vec<Expr>>, types: &[Expr]) -> Option<Vec<Expr>> {
+        let (ty, rest_types) = match types.split_first() {
+            Some(split) => split,
+            None => return rows.is_empty().then(Vec::new),
+        };
+        let any = || Expr::Var("_".to_string());
+        // The column of variables alone doesn't need to be taken apart
+        let constructors = match ty {
+            Expr::Sym(name) if rows.iter().any(|row| !matches!(row[0], Expr::Var(_))) => {
+                self.data_types.get(name)
+            }
+            _ => None,
+        };
+        let Some(constructors) = constructors else {
+            let rows = rows
+                .into_iter()
+                .filter(|row| matches!(row[0], Expr::Var(_)))
+                .map(|row| row[1..].to_vec())
+                .collect();
+            return self.missing_case(rows, rest_types).map(|mut missing| {
+                missing.insert(0, any());
+                missing
+            });
+        };
+        for constructor in constructors {
+            let (head, arg_types) = match constructor {
+                Expr::Fun(head, arg_types) => (&**head, &arg_types[..]),
+                _ => (constructor, &[][..]),
+            };
+            let arity = arg_types.len();
+            let rows = rows
+                .iter()
+                .filter_map(|row| {
+                    let mut specialized = match &row[0] {
+                        Expr::Var(_) => vec![any(); arity],
+                        Expr::Fun(pattern_head, args)
+                            if **pattern_head == *head && args.len() == arity =>
+                        {
+                            args.clone()
+                        }
+                        pattern if *pattern == *head && arity == 0 => vec![],
+                        _ => return None,
+                    };
+                    specialized.extend_from_slice(&row[1..]);
+                    Some(specialized)
+                })
+                .collect();
+            let mut types = arg_types.to_vec();
+            types.extend_from_slice(rest_types);
+            if let Some(mut missing) = self.missing_case(rows, &types) {
+                let args: Vec<Expr> = missing.drain(..arity).collect();
+                let value = if args.is_empty() {
+                    head.clone()
+                } else {
+                    Expr::Fun(Box::new(head.clone()), args)
+                };
+                missing.insert(0, value);
+                return Some(missing);
+            }
         }
+        None
     }
-}
 
-struct Context {
-    rules: HashMap<String, Rule>,
-    shaping_stack: Vec<ShapingFrame>,
-    history: Vec<Command>,
-    quit: bool,
-}
+    /// Checks whether the sides of the equation are equal, either
+    /// structurally or modulo the declared AC theory. Returns `None`
+    /// if the expression is not an equation.
+    fn equation_closed(&self, expr: &Expr) -> Option<bool> {
+        match expr {
+            Expr::Op(Op::Eql, lhs, rhs) => Some(
+                lhs == rhs || lhs.ac_normalized(&self.ac_ops) == rhs.ac_normalized(&self.ac_ops),
+            ),
+            _ => None,
+        }
+    }
 
-fn pad(sink: &mut impl Write, width: usize) -> io::Result<()> {
-    write!(sink, "{:>width$}", "")
-}
+    fn report_closed_equation(&self, expr: &Expr) {
+        if self.equation_closed(expr) == Some(true) {
+            println!("the equation is closed");
+        }
+    }
 
-impl Context {
-    fn new() -> Self {
-        let mut rules = HashMap::new();
-        // TODO: you can potentially `delete` the replace rule (you should not be able to do that)
-        rules.insert("replace".to_string(), Rule::Replace);
-        Self {
-            rules,
-            shaping_stack: Default::default(),
-            quit: false,
-            history: Default::default(),
+    /// Applies the rule to the chosen side of the equation
+    fn apply_rule_to_side(
+        &self,
+        rule: &Rule,
+        expr: &Expr,
+        strategy: &Strategy,
+        traversal: &Traversal,
+        side: Side,
+        loc: &Loc,
+    ) -> Result<Expr, RuntimeError> {
+        let (lhs, rhs) = match expr {
+            Expr::Op(Op::Eql, lhs, rhs) => (lhs, rhs),
+            _ => return Err(RuntimeError::NotAnEquation(expr.clone(), loc.clone())),
+        };
+        let (new_lhs, new_rhs) = match side {
+            Side::Left => (
+                rule.apply(lhs, strategy, traversal, self, loc)?,
+                *rhs.clone(),
+            ),
+            Side::Right => (
+                *lhs.clone(),
+                rule.apply(rhs, strategy, traversal, self, loc)?,
+            ),
+            Side::Both => {
+                let new_lhs = rule.apply(lhs, strategy, traversal, self, loc);
+                let new_rhs = rule.apply(rhs, strategy, traversal, self, loc);
+                match (new_lhs, new_rhs) {
+                    (Err(RuntimeError::NoMatch(_)), Err(err)) => return Err(err),
+                    (Err(RuntimeError::NoMatch(_)), Ok(new_rhs)) => (*lhs.clone(), new_rhs),
+                    (Ok(new_lhs), Err(RuntimeError::NoMatch(_))) => (new_lhs, *rhs.clone()),
+                    (new_lhs, new_rhs) => (new_lhs?, new_rhs?),
+                }
+            }
+        };
+        Ok(Expr::Op(Op::Eql, Box::new(new_lhs), Box::new(new_rhs)))
+    }
+
+    /// Removes the command from the history keeping the positions
+    /// remembered by the shaping frames in sync
+    fn remove_from_history(&mut self, index: usize) -> Command {
+        let frames = self
+            .shaping_stack
+            .iter_mut()
+            .chain(self.finished_frames.iter_mut().map(|(frame, _)| frame));
+        for frame in frames {
+            let step_indices = frame.history.iter_mut().map(|step| &mut step.index);
+            let focus_index = frame.focus.iter_mut().map(|focus| &mut focus.opened_at);
+            for step_index in step_indices.chain(focus_index) {
+                match step_index {
+                    Some(step_index) if *step_index > index => *step_index -= 1,
+                    _ => {}
+                }
+            }
+        }
+        for (_, finish_index) in self.finished_frames.iter_mut() {
+            if *finish_index > index {
+                *finish_index -= 1
+            }
         }
+        self.history.remove(index)
     }
 
-    fn save_history(&self, file_path: &str) -> Result<(), io::Error> {
-        let mut sink = fs::File::create(file_path)?;
-        let mut indent = 0;
-        for command in self.history.iter() {
-            match command {
-                Command::DefineRule(_, name, rule) => match rule {
-                    Rule::User { head, body, .. } => {
-                        pad(&mut sink, indent * 2)?;
-                        writeln!(sink, "{} :: {} = {}", name, head, body)?
-                    }
-                    Rule::Replace => {
-                        unreachable!("There is no way for the user to create such rule")
+    fn undo(&mut self, loc: &Loc) -> Result<(), RuntimeError> {
+        let just_finished = match (self.finished_frames.last(), self.history.len()) {
+            (Some((_, index)), len) => *index + 1 == len,
+            _ => false,
+        };
+        if just_finished {
+            let (mut frame, index) = self
+                .finished_frames
+                .pop()
+                .expect("The finished frame was checked above");
+            let command = self.remove_from_history(index);
+            for (name, rule) in frame.assumptions.iter().chain(&frame.goals.hypotheses) {
+                self.rules.insert(name.clone(), rule.clone());
+            }
+            if let Some(rule) = &frame.rule_via_shaping {
+                self.take_rule(&rule.name);
+                println!("removed rule `{}`", rule.name);
+                if let Some((replaced, tags, shaping)) = frame.replaced_rule.take() {
+                    self.rules.insert(rule.name.clone(), replaced);
+                    if let Some(tags) = tags {
+                        self.rule_tags.insert(rule.name.clone(), tags);
                     }
-                },
-                Command::DefineRuleViaShaping { name, expr } => {
-                    pad(&mut sink, indent * 2)?;
-                    writeln!(sink, "{} :: {} {{", name, expr)?;
-                    indent += 1
-                }
-                Command::StartShaping(_, expr) => {
-                    pad(&mut sink, indent * 2)?;
-                    writeln!(sink, "{} {{", expr)?;
-                    indent += 1
-                }
-                Command::ApplyRule {
-                    strategy_name,
-                    applied_rule,
-                    ..
-                } => {
-                    pad(&mut sink, indent * 2)?;
-                    match applied_rule {
-                        AppliedRule::ByName { name, reversed, .. } => {
-                            if *reversed {
-                                writeln!(sink, "{} |! {}", name, strategy_name)?
-                            } else {
-                                writeln!(sink, "{} | {}", name, strategy_name)?
-                            }
-                        }
-                        AppliedRule::Anonymous { head, body, .. } => {
-                            writeln!(sink, "{} = {} | {}", head, body, strategy_name)?
-                        }
+                    if let Some(shaping) = shaping {
+                        self.rule_origins.insert(rule.name.clone(), shaping);
                     }
+                    println!("restored the previous definition of rule `{}`", rule.name);
                 }
-                Command::FinishShaping(_) => {
-                    indent -= 1;
-                    pad(&mut sink, indent * 2)?;
-                    writeln!(sink, "}}")?
-                }
-                Command::UndoRule(_) => {
-                    pad(&mut sink, indent * 2)?;
-                    writeln!(sink, "undo")?
-                }
-                Command::Quit => {
-                    pad(&mut sink, indent * 2)?;
-                    writeln!(sink, "quit")?
-                }
-                Command::DeleteRule(_, name) => {
-                    pad(&mut sink, indent * 2)?;
-                    writeln!(sink, "delete {}", name)?
-                }
-                Command::Load(_, name) => {
-                    pad(&mut sink, indent * 2)?;
-                    writeln!(sink, "load \"{}\"", name)?
-                }
-                Command::Save(_, name) => {
-                    pad(&mut sink, indent * 2)?;
-                    writeln!(sink, "save \"{}\"", name)?
+            }
+            if let Some(focus) = frame.focus.as_mut() {
+                if let Some(parent_expr) = focus.parent_expr.take() {
+                    let parent = self
+                        .shaping_stack
+                        .last_mut()
+                        .expect("The focused shaping always has a parent");
+                    parent.expr = parent_expr;
+                    let goals = std::mem::take(&mut focus.parent_goals);
+                    let hypotheses = std::mem::replace(&mut parent.goals, goals).hypotheses;
+                    let previous_hypotheses = parent.goals.hypotheses.clone();
+                    self.switch_hypotheses(&hypotheses, &previous_hypotheses);
                 }
             }
+            println!(" => {}", &frame.expr);
+            self.shaping_stack.push(frame);
+            self.undone.push(command);
+            return Ok(());
+        }
+
+        let frame = match self.shaping_stack.last_mut() {
+            Some(frame) => frame,
+            None => return Err(RuntimeError::NoShapingInPlace(loc.clone())),
+        };
+        if let (
+            None,
+            Some(Focus {
+                opened_at: Some(index),
+                ..
+            }),
+        ) = (frame.history.last(), &frame.focus)
+        {
+            // Undoing the first step of the focused shaping leaves it
+            let index = *index;
+            self.shaping_stack.pop();
+            let parent = self
+                .shaping_stack
+                .last()
+                .expect("The focused shaping always has a parent");
+            println!(" => {}", &parent.expr);
+            let command = self.remove_from_history(index);
+            self.undone.push(command);
+            return Ok(());
+        }
+        let (previous_expr, index) = match frame.history.last() {
+            Some(ShapingStep {
+                previous_expr,
+                index: Some(index),
+                ..
+            }) => (previous_expr.clone(), *index),
+            _ => return Err(RuntimeError::EndOfHistory(loc.clone())),
+        };
+        let step = frame.history.pop().expect("The step was checked above");
+        let hypotheses = std::mem::replace(&mut frame.goals, step.previous_goals).hypotheses;
+        let previous_hypotheses = frame.goals.hypotheses.clone();
+        self.switch_hypotheses(&hypotheses, &previous_hypotheses);
+        let frame = self
+            .shaping_stack
+            .last_mut()
+            .expect("The frame was checked above");
+        if let Command::Assume { .. } = step.command {
+            let (name, _) = frame
+                .assumptions
+                .pop()
+                .expect("Every assume step has its assumption");
+            self.rules.remove(&name);
+            println!("removed assumption `{}`", name);
         }
+        println!(" => {}", &previous_expr);
+        frame.expr = previous_expr;
+        let command = self.remove_from_history(index);
+        self.undone.push(command);
         Ok(())
     }
 
+    fn save_history(&self, file_path: &str) -> Result<(), io::Error> {
+        // The paths of the loads are relative to the files they come
+        // from, so they are written as the full paths of the loaded files
+        let history: Vec<Command> = self
+            .history
+            .iter()
+            .map(|command| match command {
+                Command::Load(loc, path, module) => {
+                    let resolved = Self::resolve_load_path(loc, path);
+                    let resolved = fs::canonicalize(&resolved).unwrap_or(resolved);
+                    Command::Load(loc.clone(), resolved.display().to_string(), module.clone())
+                }
+                command => command.clone(),
+            })
+            .collect();
+        let mut sink = fs::File::create(file_path)?;
+        write_commands(&mut sink, &history)
+    }
+
     fn process_command(&mut self, command: Command) -> Result<(), Error> {
+        // Only the commands that change the rules or the shapings make
+        // the undone commands impossible to redo. The failed ones change
+        // nothing, so the undone commands are kept.
+        let changes_state = matches!(
+            command,
+            Command::DefineRule { .. }
+                | Command::DefineDerivedRule { .. }
+                | Command::DefineRuleViaShaping { .. }
+                | Command::Assume { .. }
+                | Command::Data { .. }
+                | Command::DeleteRule(..)
+                | Command::Load(..)
+                | Command::StartShaping(..)
+                | Command::ApplyRule { .. }
+                | Command::Simp(..)
+                | Command::Prove { .. }
+                | Command::Cases { .. }
+                | Command::Induction { .. }
+                | Command::Focus(..)
+                | Command::FinishShaping(..)
+        );
+        self.execute_command(command)?;
+        if changes_state {
+            self.undone.clear();
+        }
+        Ok(())
+    }
+
+    fn execute_command(&mut self, command: Command) -> Result<(), Error> {
         match command.clone() {
-            Command::Load(loc, file_path) => {
-                let source = match fs::read_to_string(&file_path) {
-                    Ok(source) => source,
-                    Err(err) => return Err(RuntimeError::CouldNotLoadFile(loc, err).into()),
-                };
-                let mut lexer = Lexer::new(source.chars(), Some(file_path));
-                while lexer.peek_token().kind != TokenKind::End {
-                    // TODO: the processed command during the file loading should not be put into the history
-                    self.process_command(Command::parse(&mut lexer)?)?
+            Command::Load(loc, file_path, module) => {
+                if !self.load_file(&loc, &file_path, module.as_deref())? {
+                    return Ok(());
                 }
             }
-            Command::DefineRule(rule_loc, rule_name, rule) => {
-                if let Some(existing_rule) = self.rules.get(&rule_name) {
-                    let loc = match existing_rule {
-                        Rule::User { loc, .. } => Some(loc),
-                        Rule::Replace => None,
-                    };
-                    return Err(
-                        RuntimeError::RuleAlreadyExists(rule_name, rule_loc, loc.cloned()).into(),
-                    );
+            Command::Open(loc, module) => {
+                if !self.modules.contains(&module) {
+                    return Err(RuntimeError::UnknownModule(module, loc).into());
+                }
+                if !self.opened.contains(&module) {
+                    self.opened.push(module);
+                }
+            }
+            Command::DefineRule {
+                loc,
+                name,
+                rule,
+                tags,
+                redefine,
+            } => {
+                let name = self.qualified_name(&name);
+                self.check_rule_definition(&name, &loc, redefine)?;
+                self.check_defined_rule(&rule, &loc)?;
+                if self.take_rule(&name).is_some() {
+                    println!("redefined rule `{}`", &name);
+                    self.warn_about_dependents(&name, "redefined");
+                } else {
+                    println!("defined rule `{}`", &name);
+                }
+                if !tags.is_empty() {
+                    self.rule_tags.insert(name.clone(), tags);
+                }
+                self.rules.insert(name, rule);
+            }
+            Command::DefineDerivedRule {
+                loc,
+                name,
+                derivation,
+                tags,
+                redefine,
+            } => {
+                let name = self.qualified_name(&name);
+                self.check_rule_definition(&name, &loc, redefine)?;
+                let rule = self.derive_rule(&derivation, &loc)?;
+                self.check_defined_rule(&rule, &loc)?;
+                if self.take_rule(&name).is_some() {
+                    println!("redefined rule `{}`", &name);
+                    self.warn_about_dependents(&name, "redefined");
+                } else {
+                    println!("defined rule `{}`", &name);
+                }
+                for clause in rule.clauses() {
+                    if let Rule::User { head, body, .. } = clause {
+                        println!(" => {} = {}", head, body);
+                    }
+                }
+                if !tags.is_empty() {
+                    self.rule_tags.insert(name.clone(), tags);
+                }
+                self.rule_origins
+                    .insert(name.clone(), vec![command.clone()]);
+                self.rules.insert(name, rule);
+            }
+            Command::Assume { loc, name, rule } => {
+                if self.shaping_stack.is_empty() {
+                    return Err(RuntimeError::NoShapingInPlace(loc).into());
                 }
-                println!("defined rule `{}`", &rule_name);
-                self.rules.insert(rule_name, rule);
+                let name = self.qualified_name(&name);
+                self.check_rule_definition(&name, &loc, false)?;
+                self.check_defined_rule(&rule, &loc)?;
+                println!("assumed rule `{}`", &name);
+                self.rules.insert(name.clone(), rule.clone());
+                let expr = self
+                    .shaping_stack
+                    .last()
+                    .expect("The shaping frame was checked above")
+                    .expr
+                    .clone();
+                // The assumption is a step of the shaping that leaves the
+                // expression as is, so it can be undone like any other step
+                self.push_shaping_step(expr, &command);
+                self.shaping_stack
+                    .last_mut()
+                    .expect("The shaping frame was checked above")
+                    .assumptions
+                    .push((name, rule));
             }
-            Command::DefineRuleViaShaping { name, expr, .. } => {
+            Command::DefineRuleViaShaping {
+                name,
+                expr,
+                target,
+                forall,
+                tags,
+                redefine,
+            } => {
                 println!(" => {}", &expr);
+                self.report_closed_equation(&expr);
+                let rule = RuleViaShaping {
+                    name: self.qualified_name(&name),
+                    head: expr,
+                    forall,
+                    tags,
+                    redefine,
+                };
                 self.shaping_stack
-                    .push(ShapingFrame::new_rule_via_shaping(name, expr))
+                    .push(ShapingFrame::new_rule_via_shaping(rule, target))
             }
             Command::StartShaping(_loc, expr) => {
                 println!(" => {}", &expr);
+                self.report_closed_equation(&expr);
                 self.shaping_stack.push(ShapingFrame::new(expr))
             }
             Command::ApplyRule {
                 loc,
                 strategy_name,
+                traversal,
+                applied_rule,
+                side,
+            } => {
+                let expr = match self.shaping_stack.last() {
+                    Some(frame) => frame.expr.clone(),
+                    None => return Err(RuntimeError::NoShapingInPlace(loc).into()),
+                };
+                let rule = self.resolve_applied_rule(applied_rule)?;
+                let rule = self.handle_unbound(rule, &expr, &loc)?;
+                let strategy = match Strategy::by_name(&strategy_name) {
+                    Some(strategy) => strategy,
+                    None => return Err(RuntimeError::UnknownStrategy(strategy_name, loc).into()),
+                };
+                let new_expr = match side {
+                    Some(side) => {
+                        self.apply_rule_to_side(&rule, &expr, &strategy, &traversal, side, &loc)?
+                    }
+                    None => rule.apply(&expr, &strategy, &traversal, self, &loc)?,
+                };
+                println!(" => {}", &new_expr);
+                self.push_shaping_step(new_expr, &command);
+            }
+            Command::Matches {
+                loc,
+                traversal,
                 applied_rule,
             } => {
-                if let Some(frame) = self.shaping_stack.last_mut() {
-                    let rule = match applied_rule {
-                        AppliedRule::ByName {
+                let expr = match self.shaping_stack.last() {
+                    Some(frame) => &frame.expr,
+                    None => return Err(RuntimeError::NoShapingInPlace(loc).into()),
+                };
+                let rule = self.resolve_applied_rule(applied_rule)?;
+                let occurrences = rule.occurrences(expr, &traversal);
+                if occurrences.is_empty() {
+                    return Err(RuntimeError::NoMatch(loc).into());
+                }
+                let highlight = termion::is_tty(&stdout());
+                for (index, subexpr) in occurrences.into_iter().enumerate() {
+                    if highlight {
+                        println!("{}: {}", index, HighlightedSubexpr { expr, subexpr });
+                    } else {
+                        println!("{}: {}", index, subexpr);
+                    }
+                }
+                return Ok(());
+            }
+            Command::FinishShaping(loc, target) => {
+                let frame = match self.shaping_stack.last() {
+                    Some(frame) => frame,
+                    None => return Err(RuntimeError::NoShapingInPlace(loc).into()),
+                };
+                for target in frame.target.iter().chain(target.iter()) {
+                    if *target != frame.expr {
+                        return Err(RuntimeError::TargetNotReached {
+                            expected: target.clone(),
+                            actual: frame.expr.clone(),
                             loc,
-                            name,
-                            reversed,
-                        } => match self.rules.get(&name) {
-                            Some(rule) => {
-                                if reversed {
-                                    match rule.clone() {
-                                        Rule::User { loc, head, body } => Rule::User {
-                                            loc,
-                                            head: body,
-                                            body: head,
-                                        },
-                                        Rule::Replace => {
-                                            return Err(RuntimeError::IrreversibleRule(loc).into())
-                                        }
-                                    }
-                                } else {
-                                    rule.clone()
-                                }
-                            }
-
-                            None => return Err(RuntimeError::RuleDoesNotExist(name, loc).into()),
-                        },
-                        AppliedRule::Anonymous { loc, head, body } => {
-                            Rule::User { loc, head, body }
-                        }
-                    };
-
-                    let new_expr = match Strategy::by_name(&strategy_name) {
-                        Some(strategy) => rule.apply(&frame.expr, &strategy, &loc)?,
-                        None => {
-                            return Err(RuntimeError::UnknownStrategy(strategy_name, loc).into())
                         }
+                        .into());
+                    }
+                }
+                if !frame.goals.pending.is_empty() {
+                    return Err(RuntimeError::OpenGoals(frame.goals.pending.len(), loc).into());
+                }
+                if let Some(rule) = &frame.rule_via_shaping {
+                    if matches!(rule.head, Expr::Op(Op::Eql, ..))
+                        && self.equation_closed(&frame.expr) != Some(true)
+                    {
+                        return Err(RuntimeError::EquationNotClosed(frame.expr.clone(), loc).into());
+                    }
+                    self.check_rule_definition(&rule.name, &loc, rule.redefine)?;
+                }
+                let mut frame = self
+                    .shaping_stack
+                    .pop()
+                    .expect("The shaping frame was checked above");
+                for (name, _) in frame.assumptions.iter().chain(&frame.goals.hypotheses) {
+                    self.rules.remove(name);
+                }
+                if let Some(rule) = &frame.rule_via_shaping {
+                    let name = rule.name.clone();
+                    let replaced_rule = self.take_rule(&name);
+                    if replaced_rule.is_some() {
+                        println!("redefined rule `{}`", &name);
+                        self.warn_about_dependents(&name, "redefined");
+                    } else {
+                        println!("defined rule `{}`", &name);
+                    }
+                    if !rule.tags.is_empty() {
+                        self.rule_tags.insert(name.clone(), rule.tags.clone());
+                    }
+                    let (head, body) = match &rule.head {
+                        // The rule shaped from an equation rewrites one
+                        // of its sides into the other
+                        Expr::Op(Op::Eql, lhs, rhs) => (*lhs.clone(), *rhs.clone()),
+                        head => (head.clone(), frame.expr.clone()),
                     };
+                    let (head, body) = abstract_symbols(&rule.forall, head, body);
+                    if !rule.forall.is_empty() {
+                        println!(" => {} = {}", head, body);
+                    }
+                    let mut shaping = vec![Command::DefineRuleViaShaping {
+                        name: name.clone(),
+                        expr: rule.head.clone(),
+                        target: frame.target.clone(),
+                        forall: rule.forall.clone(),
+                        tags: rule.tags.clone(),
+                        redefine: false,
+                    }];
+                    shaping.extend(frame.history.iter().map(|step| step.command.clone()));
+                    shaping.push(Command::FinishShaping(loc.clone(), target));
+                    self.rule_origins.insert(name.clone(), shaping);
+                    let params = vec![];
+                    self.rules.insert(
+                        name,
+                        Rule::User {
+                            loc,
+                            head,
+                            body,
+                            params,
+                        },
+                    );
+                    frame.replaced_rule = replaced_rule;
+                }
+                if let Some(focus) = &mut frame.focus {
+                    let parent = self
+                        .shaping_stack
+                        .last()
+                        .expect("The focused shaping always has a parent");
+                    let mut new_expr = parent.expr.clone();
+                    *new_expr
+                        .subexpr_at_mut(&focus.path)
+                        .expect("The path was checked when the focus was set") = frame.expr.clone();
                     println!(" => {}", &new_expr);
-                    frame.history.push(new_expr.clone());
-                    frame.expr = new_expr;
+                    let (new_expr, goals) = self.advance_goals(new_expr, parent.goals.clone());
+                    let parent = self
+                        .shaping_stack
+                        .last_mut()
+                        .expect("The focused shaping always has a parent");
+                    focus.parent_expr = Some(std::mem::replace(&mut parent.expr, new_expr));
+                    let previous_hypotheses = parent.goals.hypotheses.clone();
+                    let hypotheses = goals.hypotheses.clone();
+                    focus.parent_goals = std::mem::replace(&mut parent.goals, goals);
+                    self.switch_hypotheses(&previous_hypotheses, &hypotheses);
+                }
+                if self.records_history() {
+                    self.finished_frames.push((frame, self.history.len()));
+                }
+            }
+            Command::Focus(loc, path) => {
+                let opened_at = if self.records_history() {
+                    Some(self.history.len())
                 } else {
-                    return Err(RuntimeError::NoShapingInPlace(loc).into());
+                    None
+                };
+                let frame = match self.shaping_stack.last_mut() {
+                    Some(frame) => frame,
+                    None => return Err(RuntimeError::NoShapingInPlace(loc).into()),
+                };
+                let expr = match frame.expr.subexpr_at_mut(&path) {
+                    Some(expr) => expr.clone(),
+                    None => {
+                        return Err(
+                            RuntimeError::NoSubexpression(path, frame.expr.clone(), loc).into()
+                        )
+                    }
+                };
+                println!(" => {}", &expr);
+                let focus = Focus {
+                    path,
+                    opened_at,
+                    parent_expr: None,
+                    parent_goals: Goals::default(),
+                };
+                self.shaping_stack
+                    .push(ShapingFrame::new_focus(expr, focus));
+            }
+            Command::Cases {
+                loc,
+                subject,
+                patterns,
+            } => {
+                let goal = self.goal_to_split(&subject, &loc)?;
+                let goal_vars = goal.vars();
+                for pattern in &patterns {
+                    for name in pattern.vars() {
+                        if goal_vars.contains(&name) && subject != Expr::Var(name.clone()) {
+                            return Err(RuntimeError::CaseVariableTaken(name, loc).into());
+                        }
+                    }
                 }
+                self.check_cases_exhaustive(&patterns, &loc)?;
+                let cases = patterns
+                    .into_iter()
+                    .map(|pattern| (pattern, vec![]))
+                    .collect();
+                self.split_goal(&subject, cases, &command);
             }
-            Command::FinishShaping(loc) => {
-                if let Some(mut frame) = self.shaping_stack.pop() {
-                    let body = frame.expr;
-                    if let Some((name, head)) = frame.rule_via_shaping.take() {
-                        if let Some(existing_rule) = self.rules.get(&name) {
-                            let old_loc = match existing_rule {
-                                Rule::User { loc, .. } => Some(loc.clone()),
-                                Rule::Replace => None,
-                            };
-                            return Err(RuntimeError::RuleAlreadyExists(name, loc, old_loc).into());
+            Command::Induction { loc, subject, data } => {
+                let goal = self.goal_to_split(&subject, &loc)?;
+                let cases = self.induction_cases(&goal, &subject, &data, &loc)?;
+                for (pattern, hypotheses) in &cases {
+                    for (name, hypothesis) in hypotheses {
+                        if let Rule::User { head, body, .. } = hypothesis {
+                            println!(
+                                "hypothesis `{}` for {} := {}: {} = {}",
+                                name, subject, pattern, head, body
+                            );
                         }
-                        println!("defined rule `{}`", &name);
-                        self.rules.insert(name, Rule::User { loc, head, body });
                     }
-                } else {
-                    return Err(RuntimeError::NoShapingInPlace(loc).into());
                 }
+                self.split_goal(&subject, cases, &command);
             }
-            Command::UndoRule(loc) => {
-                if let Some(frame) = self.shaping_stack.last_mut() {
-                    if let Some(previous_expr) = frame.history.pop() {
-                        println!(" => {}", &previous_expr);
-                        frame.expr = previous_expr;
-                    } else {
-                        return Err(RuntimeError::EndOfHistory(loc).into());
+            Command::Generalize(_loc, first, second) => {
+                let pattern = first.anti_unify(&second);
+                println!(" => {}", pattern);
+                for example in [&first, &second] {
+                    let bindings = pattern
+                        .pattern_match(example)
+                        .expect("The generalization matches both of the expressions");
+                    // The variables of the examples themselves stay as they are
+                    let mut bindings: Vec<(&String, &Expr)> = bindings
+                        .iter()
+                        .filter(|(name, value)| **value != Expr::Var(name.to_string()))
+                        .collect();
+                    bindings.sort_by_key(|(name, _)| *name);
+                    let bindings: Vec<String> = bindings
+                        .iter()
+                        .map(|(name, value)| format!("{} := {}", name, value))
+                        .collect();
+                    if !bindings.is_empty() {
+                        println!("  {} for {}", bindings.join(", "), example);
                     }
-                } else {
-                    return Err(RuntimeError::NoShapingInPlace(loc).into());
                 }
+                return Ok(());
+            }
+            Command::Data {
+                loc,
+                name,
+                constructors,
+            } => {
+                if self.data_types.contains_key(&name) {
+                    return Err(RuntimeError::DataAlreadyExists(name, loc).into());
+                }
+                let mut heads = Vec::new();
+                for constructor in &constructors {
+                    let (head, arg_types) = match constructor {
+                        Expr::Fun(head, arg_types) => (&**head, &arg_types[..]),
+                        _ => (constructor, &[][..]),
+                    };
+                    if heads.contains(&head) {
+                        return Err(RuntimeError::DuplicateConstructor(head.clone(), loc).into());
+                    }
+                    heads.push(head);
+                    // The arguments are either of the declared type itself
+                    // or of the types declared before it
+                    for arg_type in arg_types {
+                        if let Expr::Sym(arg_type) = arg_type {
+                            if *arg_type != name && !self.data_types.contains_key(arg_type) {
+                                return Err(RuntimeError::UnknownData(arg_type.clone(), loc).into());
+                            }
+                        }
+                    }
+                }
+                println!("declared data type `{}`", name);
+                self.data_types.insert(name, constructors);
+            }
+            Command::Theory(_loc, ops) => {
+                self.ac_ops.extend(ops.iter().copied());
+                let ops: Vec<String> = ops.iter().map(|op| format!("`{}`", op)).collect();
+                println!("declared {} associative and commutative", ops.join(", "));
+            }
+            Command::Simp(loc) => {
+                let expr = match self.shaping_stack.last() {
+                    Some(frame) => frame.expr.clone(),
+                    None => return Err(RuntimeError::NoShapingInPlace(loc).into()),
+                };
+                let (new_expr, used_rules) = self.simplify(&expr, &loc)?;
+                if used_rules.is_empty() {
+                    return Err(RuntimeError::NoMatch(loc).into());
+                }
+                let used_rules: Vec<String> = used_rules
+                    .iter()
+                    .map(|name| format!("`{}`", name))
+                    .collect();
+                println!("used rules {}", used_rules.join(", "));
+                println!(" => {}", &new_expr);
+                self.push_shaping_step(new_expr, &command);
+            }
+            Command::Prove {
+                loc,
+                start,
+                goal,
+                rule_names,
+                depth,
+            } => {
+                let rule_names = rule_names.unwrap_or_else(|| {
+                    let mut names: Vec<String> = self
+                        .rules
+                        .iter()
+                        .filter(|(_, rule)| {
+                            !matches!(rule, Rule::Replace) && rule.params().is_empty()
+                        })
+                        .map(|(name, _)| name.clone())
+                        .collect();
+                    names.sort();
+                    names
+                });
+                let shaping = self.prove(&loc, &start, &goal, &rule_names, depth)?;
+                println!("found shaping in {} steps:", shaping.len() - 2);
+                write_commands(&mut stdout(), &shaping)
+                    .expect("Writing to the standard output should not fail");
+                if self.records_history() {
+                    self.history.extend(shaping);
+                }
+                return Ok(());
+            }
+            Command::Limit(_loc, limit, value) => {
+                self.limits.set(limit, value);
+            }
+            Command::Unbound(_loc, unbound) => {
+                self.unbound = unbound;
+            }
+            Command::UndoRule(loc, count) => {
+                for _ in 0..count {
+                    self.undo(&loc)?;
+                }
+                return Ok(());
+            }
+            Command::Redo(loc, count) => {
+                for _ in 0..count {
+                    let command = match self.undone.pop() {
+                        Some(command) => command,
+                        None => return Err(RuntimeError::EndOfHistory(loc).into()),
+                    };
+                    // Redoing must not forget the rest of the undone commands
+                    let undone = std::mem::take(&mut self.undone);
+                    let result = self.process_command(command.clone());
+                    self.undone = undone;
+                    if result.is_err() {
+                        self.undone.push(command);
+                    }
+                    result?;
+                }
+                return Ok(());
             }
             Command::Quit => {
                 self.quit = true;
+                return Ok(());
+            }
+            Command::ListRules(_loc, pattern) => {
+                let mut names: Vec<&String> = self
+                    .rules
+                    .iter()
+                    .filter(|(_, rule)| match (&pattern, rule) {
+                        (None, _) => true,
+                        (Some(pattern), rule) => rule.clauses().iter().any(|clause| {
+                            matches!(clause, Rule::User { head, .. }
+                                if pattern.pattern_match(head).is_some())
+                        }),
+                    })
+                    .map(|(name, _)| name)
+                    .collect();
+                names.sort();
+                let mut sink = stdout();
+                for name in names {
+                    let tags = self.rule_tags.get(name).map_or(&[][..], |tags| tags);
+                    write_rule(&mut sink, name, &self.rules[name], tags, 0)
+                        .expect("Writing to the standard output should not fail");
+                }
+                return Ok(());
+            }
+            Command::ShowRule(loc, name) => {
+                let name = self.resolve_rule_name(&name, &loc)?;
+                let rule = &self.rules[&name];
+                let tags = self.rule_tags.get(&name).cloned().unwrap_or_default();
+                let mut sink = stdout();
+                write_rule(&mut sink, &name, rule, &tags, 0)
+                    .expect("Writing to the standard output should not fail");
+                if let Some(loc) = rule.loc() {
+                    println!("defined at {}", loc);
+                    if let Some(origin) = self.rule_origins.get(&name) {
+                        match origin.first() {
+                            Some(Command::DefineDerivedRule { .. }) => println!("derived as:"),
+                            _ => println!("via shaping:"),
+                        }
+                        write_commands(&mut sink, origin)
+                            .expect("Writing to the standard output should not fail");
+                    }
+                }
+                return Ok(());
             }
             Command::DeleteRule(loc, name) => {
-                if self.rules.contains_key(&name) {
-                    self.rules.remove(&name);
-                } else {
-                    return Err(RuntimeError::RuleDoesNotExist(name, loc).into());
+                let name = self.resolve_rule_name(&name, &loc)?;
+                if let Rule::Replace = self.rules[&name] {
+                    return Err(RuntimeError::BuiltinRule(name, loc).into());
                 }
+                self.take_rule(&name);
+                self.warn_about_dependents(&name, "deleted");
             }
             Command::Save(loc, file_path) => {
                 self.save_history(&file_path)
                     .map_err(|err| RuntimeError::CouldNotSaveFile(loc.clone(), err))?;
+                if !self.shaping_stack.is_empty() {
+                    println!(
+                        "saved session with {} unfinished shaping(s)",
+                        self.shaping_stack.len()
+                    );
+                }
+                // Restoring the session must not overwrite the file it
+                // is restored from
+                return Ok(());
             }
         }
-        self.history.push(command);
+        if self.records_history() {
+            self.history.push(command);
+        }
         Ok(())
     }
 }
@@ -1035,9 +5499,12 @@ fn parse_and_process_command(
     Ok(())
 }
 
-fn interpret_file(file_path: &str) {
+/// Interprets the file and returns the resulting context. A file that
+/// ends in the middle of a shaping is a saved session.
+fn interpret_file(file_path: &str) -> Context {
     let mut context = Context::new();
-    let source = fs::read_to_string(&file_path).unwrap();
+    context.main_file = fs::canonicalize(file_path).ok();
+    let source = fs::read_to_string(file_path).unwrap();
     let mut lexer = Lexer::new(source.chars(), Some(file_path.to_string()));
     while !context.quit && lexer.peek_token().kind != TokenKind::End {
         if let Err(err) = parse_and_process_command(&mut context, &mut lexer) {
@@ -1050,10 +5517,10 @@ fn interpret_file(file_path: &str) {
             std::process::exit(1);
         }
     }
+    context
 }
 
-fn start_repl() {
-    let mut context = Context::new();
+fn start_repl(mut context: Context) {
     let mut command = String::new();
 
     let default_prompt = "noq> ";
@@ -1065,17 +5532,33 @@ fn start_repl() {
         if context.shaping_stack.is_empty() {
             prompt = default_prompt;
         } else {
-            shaping_prompt = format!("{}> ", context.shaping_stack.len());
+            let depth = context.shaping_stack.len();
+            shaping_prompt = match context
+                .shaping_stack
+                .last()
+                .and_then(|f| f.goals.active.as_ref())
+            {
+                Some(case) => format!("{} [{}]> ", depth, case),
+                None => format!("{}> ", depth),
+            };
             prompt = &shaping_prompt;
         }
         print!("{}", prompt);
         stdout().flush().unwrap();
-        stdin().read_line(&mut command).unwrap();
+        if stdin().read_line(&mut command).unwrap() == 0 {
+            println!();
+            break;
+        }
         let mut lexer = Lexer::new(command.trim().chars(), None);
         if lexer.peek_token().kind != TokenKind::End {
             if let Err(err) = repl_parse_and_process_command(&mut context, &mut lexer) {
-                eprint_repl_loc_cursor(prompt, err.loc());
-                eprintln!("ERROR: {}", err);
+                if err.loc().file_path.is_some() {
+                    // The error happened in a loaded file
+                    eprintln!("{}: ERROR: {}", err.loc(), err);
+                } else {
+                    eprint_repl_loc_cursor(prompt, err.loc());
+                    eprintln!("ERROR: {}", err);
+                }
                 if let Error::Runtime(RuntimeError::RuleAlreadyExists(_, _, Some(prev_loc))) = err {
                     if prev_loc.file_path.is_some() {
                         eprintln!("{}: previous declaration is located here", prev_loc)
@@ -1143,6 +5626,8 @@ impl Config {
 }
 
 fn start_new_cool_repl() {
+    // TODO: report the syntax errors of the match in the popup
+    #[allow(dead_code)]
     enum MatchSyntaxError {
         Head(expr::SyntaxError),
         Separator(Token),
@@ -1220,10 +5705,20 @@ fn main() {
     let config = Config::from_iter(&mut env::args());
 
     if let Some(file_path) = &config.file_path {
-        interpret_file(file_path)
+        let context = interpret_file(file_path);
+        if !context.quit {
+            if let Some(frame) = context.shaping_stack.last() {
+                println!(
+                    "resuming session with {} unfinished shaping(s)",
+                    context.shaping_stack.len()
+                );
+                println!(" => {}", frame.expr);
+                start_repl(context)
+            }
+        }
     } else {
         match config.mode {
-            ReplMode::Normal => start_repl(),
+            ReplMode::Normal => start_repl(Context::new()),
             ReplMode::DebugNew => start_new_cool_repl(),
             ReplMode::DebugParser => start_parser_debugger(),
             ReplMode::DebugLexer => start_lexer_debugger(),
@@ -1231,6 +5726,4 @@ fn main() {
     }
 }
 
-// TODO: `undo` command should remove previous command from the history
-// TODO: Ability to restore saved session
 // TODO: Custom arbitrary operators like in Haskell
@@ -148,7 +148,9 @@ pub struct HighlightedSubexpr<'a> {
 impl<'a> fmt::Display for HighlightedSubexpr<'a> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         let HighlightedSubexpr { expr, subexpr } = self;
-        if expr == subexpr {
+        // The subexpression is compared by address, so only that exact
+        // occurrence is highlighted and not the equal ones
+        if std::ptr::eq(*expr, *subexpr) {
             write!(
                 f,
                 "{}{}{}",